@@ -12,6 +12,6 @@ fn main() {
         num_bits,
         num_features,
     );
-    let bit_code = bit_codes::encoding::string_to_bit_code(&string, &encoding_options);
+    let bit_code = bit_codes::encoding::string_to_bit_code(string, &encoding_options);
     println!("{:?}", bit_code);
 }
@@ -18,7 +18,7 @@ fn main() {
     // Create bit code pool from random strings.
     let encoding_options = bit_codes::encoding_options::EncodingOptions::new(downcase, ngram_lengths, num_bits, num_features);
     let mut bit_code_pool = bit_codes::bit_code_pool::BitCodePool::new(encoding_options);
-    for i in 0..strings.len() { bit_code_pool.add(&strings[i], i as u64); }
+    for (i, string) in strings.iter().enumerate() { bit_code_pool.add(string, i as u64); }
     // Resolve entities in bit code pool.
     let t1 = time::precise_time_s();
     let entity_sets = bit_code_pool.resolve_entities(radius);
@@ -27,7 +27,7 @@ fn main() {
     for entity_set in &entity_sets {
         if entity_set.len() > 1 {
             for i in entity_set { println!("{:?}", strings[*i]); }
-            println!("");
+            println!();
         }
     }
     println!("Resolved entities into {:} entity sets of bit code pool in {:}s.", entity_sets.len(), t_s);
@@ -6,7 +6,6 @@ use rand::distributions::IndependentSample;
 
 fn main() {
     // Parameters.
-    let bits_per_index = 10;
     let downcase = true;
     let ngram_lengths = vec![3, 4, 5, 6, 7, 8];
     let num_bits = 256;
@@ -31,17 +30,17 @@ fn main() {
     // Create bit code pool from random strings.
     let t1 = time::precise_time_s();
     let mut bit_code_pool = bit_codes::bit_code_pool::BitCodePool::new(encoding_options);
-    for i in 0..strings.len() { bit_code_pool.add(&strings[i], i as u64); }
+    for (i, string) in strings.iter().enumerate() { bit_code_pool.add(string, i as u64); }
     let t2 = time::precise_time_s();
     let t_s = format!("{:.*}", 3, t2 - t1);
     println!("{:} bit codes inserted into pool in {:}s.", num_items, t_s);
     // Create index on the bit code pool.
     let t1 = time::precise_time_s();
-    bit_code_pool.index(bits_per_index);
-    let max_radius = bit_code_pool.index_max_searchable_radius();
+    let index_parameters = bit_code_pool.index_auto();
+    let max_radius = index_parameters.max_searchable_radius();
     let t2 = time::precise_time_s();
     let t_s = format!("{:.*}", 3, t2 - t1);
-    println!("Bit code index created in {:}s (max searchable radius is {:}).", t_s, max_radius);
+    println!("Bit code index with {:} indexes of {:} bits created in {:}s (max searchable radius is {:}).", index_parameters.num_indexes(), index_parameters.bits_per_index(), t_s, max_radius);
     // Search unindexed patterns.
     let mut rng = rand::thread_rng();
     let between = rand::distributions::Range::new(0, bit_code_pool.len());
@@ -1,103 +1,448 @@
-use bit_vec::BitVec;
 use encoding::string_to_bit_code;
 use encoding_options::EncodingOptions;
-use std::cmp::min;
-use utils::get_num_indexes;
+use error::BitCodeError;
+use similarity::{estimated_cosine, CosineEstimate};
+use std::cmp::{max, min};
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use utils::{get_num_indexes, num_blocks_needed};
 
 
-#[derive(Debug)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct BitCode {
-    bits: BitVec,
+    blocks: Vec<u64>,                       // Bits packed into u64 blocks; bit i is bit i % 64 of block i / 64.
+    num_bits: usize,                        // Number of bits in the code. Unused bits in the last block are always unset.
 }
 
 
 impl BitCode {
 
     pub fn new(num_bits: usize) -> Self {
-        BitCode{ bits: BitVec::from_elem(num_bits, false) }
+        BitCode{ blocks: vec![0; num_blocks_needed(num_bits)], num_bits }
     }
 
-    pub fn from_bools(bools: &Vec<bool>) -> Self {
-        let mut bits = BitVec::from_elem(bools.len(), false);
+    /// Returns a code from packed u64 blocks, laid out as returned by blocks(). Panics if the number of blocks does not match num_bits; any bits beyond num_bits are cleared.
+    pub fn from_blocks(blocks: Vec<u64>, num_bits: usize) -> Self {
+        assert_eq!(blocks.len(), num_blocks_needed(num_bits), "wrong number of blocks for a {}-bit code", num_bits);
+        let mut bit_code = BitCode{ blocks, num_bits };
+        bit_code.fix_last_block();
+        bit_code
+    }
+
+    pub fn from_bools(bools: &[bool]) -> Self {
+        let mut bit_code = BitCode::new(bools.len());
         for (i, b) in bools.iter().enumerate() {
-            bits.set(i, *b);
+            bit_code.set(i, *b);
         }
-        BitCode{ bits: bits }
+        bit_code
     }
 
     pub fn from_bit_string(string: &str) -> Self {
-        let mut bits = BitVec::from_elem(string.len(), false);
+        let mut bit_code = BitCode::new(string.len());
         for (i, c) in string.chars().enumerate() {
-            if c == '1' { bits.set(i, true); }
-            else { bits.set(i, false); }
+            if c == '1' { bit_code.set(i, true); }
+            else { bit_code.set(i, false); }
         }
-        BitCode{ bits: bits }
+        bit_code
     }
 
     pub fn from_string(string: &str, encoding_options: &EncodingOptions) -> Self {
         string_to_bit_code(string, encoding_options)
     }
 
+    /// Returns the bitwise majority vote of the codes, e.g. a consensus code for a cluster. A bit is set when strictly more than half of the codes have it set, so ties are unset. Returns None when no codes are given; panics if the codes differ in length.
+    pub fn majority(bit_codes: &[BitCode]) -> Option<Self> {
+        let first = bit_codes.first()?;
+        let mut counts: Vec<usize> = vec![0; first.len()];
+        for bit_code in bit_codes {
+            assert_same_len(first, bit_code);
+            for i in bit_code.ones() { counts[i] += 1; }
+        }
+        let n = bit_codes.len();
+        let bools: Vec<bool> = counts.iter().map(|c| 2 * c > n).collect();
+        Some(BitCode::from_bools(&bools))
+    }
+
+    /// Returns the u64 blocks holding the bits; bit i is bit i % 64 of block i / 64.
+    #[inline]
+    pub fn blocks(&self) -> &[u64] {
+        &self.blocks
+    }
+
+    #[inline]
+    pub fn count_ones(&self) -> usize {
+        self.view().count_ones()
+    }
+
+    /// Returns the positions at which this code and the other differ, in ascending order. Panics if the codes differ in length.
+    pub fn diff_positions(&self, other: &BitCode) -> Vec<usize> {
+        (self ^ other).ones().collect()
+    }
+
+    #[inline]
+    pub fn get(&self, bit_number: usize) -> Option<bool> {
+        self.view().get(bit_number)
+    }
+
+    /// Returns the number of bits at which the codes differ. Codes are expected to be the same length; if they are not, the shorter code is treated as if it were padded with unset bits, so every set bit beyond its end counts towards the distance. Use try_hamming_distance to reject codes of different lengths instead.
+    #[inline]
+    pub fn hamming_distance(&self, other: &BitCode) -> usize {
+        hamming_distance(&self.blocks, &other.blocks)
+    }
+
+    /// Returns the Hamming distance to the other code if it is <= radius, and None otherwise. Stops counting as soon as the running distance exceeds the radius, so is cheaper than hamming_distance for radius queries. Codes of different lengths are handled as in hamming_distance.
+    #[inline]
+    pub fn within(&self, other: &BitCode, radius: usize) -> Option<usize> {
+        within(&self.blocks, &other.blocks, radius)
+    }
+
+    /// Returns the Hamming distance between the codes, or an error if they are not the same length.
+    #[inline]
+    pub fn try_hamming_distance(&self, other: &BitCode) -> Result<usize, BitCodeError> {
+        self.view().try_hamming_distance(other.view())
+    }
+
+    /// Estimates the cosine similarity of the feature vectors the codes were projected from, with a 95% confidence interval. See similarity::estimated_cosine.
+    pub fn estimated_cosine(&self, other: &BitCode) -> CosineEstimate {
+        self.view().estimated_cosine(other.view())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.num_bits == 0
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Returns the substrings used as multi-index keys. See BitCodeRef::index_values.
+    pub fn index_values(&self, index_length: usize) -> Vec<u64> {
+        self.view().index_values(index_length)
+    }
+
+    /// Returns the length bits (at most 64) starting at bit start, packed into a u64 so that bit start is the lowest bit. Bits beyond the end of the code are unset.
+    #[inline]
+    pub fn substring(&self, start: usize, length: usize) -> u64 {
+        self.view().substring(start, length)
+    }
+
+    /// Returns an iterator over the positions of set bits, in ascending order.
+    pub fn ones(&self) -> Ones<'_> {
+        Ones::new(&self.blocks)
+    }
+
+    #[inline]
+    pub fn set(&mut self, bit_number: usize, value: bool) {
+        assert!(bit_number < self.num_bits, "bit {} out of bounds for a code of length {}", bit_number, self.num_bits);
+        let mask = 1u64 << (bit_number % 64);
+        if value { self.blocks[bit_number / 64] |= mask; } else { self.blocks[bit_number / 64] &= !mask; }
+    }
+
+    /// Returns a borrowed view of the code.
+    #[inline]
+    pub fn view(&self) -> BitCodeRef<'_> {
+        BitCodeRef{ blocks: &self.blocks, num_bits: self.num_bits }
+    }
+
+    // Clear any bits beyond len() in the last block.
+    #[inline]
+    fn fix_last_block(&mut self) {
+        let extra_bits = self.num_bits % 64;
+        if self.num_bits == 0 {
+            self.blocks[0] = 0;
+        } else if extra_bits > 0 {
+            let last = self.blocks.len() - 1;
+            self.blocks[last] &= (1u64 << extra_bits) - 1;
+        }
+    }
+}
+
+
+impl fmt::Debug for BitCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BitCode {{ bits: {} }}", self.view().bit_string())
+    }
+}
+
+
+/// A borrowed view of a bit code, such as a code stored in a BitCodePool. It has the same read-only API as BitCode.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct BitCodeRef<'a> {
+    blocks: &'a [u64],
+    num_bits: usize,
+}
+
+
+impl<'a> BitCodeRef<'a> {
+
+    // Views blocks laid out as in BitCode. Unused bits in the last block must be unset.
+    #[inline]
+    pub(crate) fn new(blocks: &'a [u64], num_bits: usize) -> Self {
+        debug_assert_eq!(blocks.len(), num_blocks_needed(num_bits));
+        BitCodeRef{ blocks, num_bits }
+    }
+
+    /// Returns the u64 blocks holding the bits; bit i is bit i % 64 of block i / 64.
+    #[inline]
+    pub fn blocks(&self) -> &'a [u64] {
+        self.blocks
+    }
+
     #[inline]
     pub fn count_ones(&self) -> usize {
         let mut n = 0;
-        for block in self.bits.storage() { n += block.count_ones(); }
+        for block in self.blocks { n += block.count_ones(); }
         n as usize
     }
 
+    /// Returns the positions at which this code and the other differ, in ascending order. Panics if the codes differ in length.
+    pub fn diff_positions(&self, other: BitCodeRef) -> Vec<usize> {
+        self.to_bit_code().diff_positions(&other.to_bit_code())
+    }
+
     #[inline]
     pub fn get(&self, bit_number: usize) -> Option<bool> {
-        self.bits.get(bit_number)
+        if bit_number >= self.num_bits { return None; }
+        Some((self.blocks[bit_number / 64] >> (bit_number % 64)) & 1 == 1)
     }
 
+    /// Returns the number of bits at which the codes differ. See BitCode::hamming_distance.
     #[inline]
-    pub fn hamming_distance(&self, other: &BitCode) -> usize {
-        let mut d: usize = 0;
-        let storage1 = self.bits.storage();
-        let storage2 = other.bits.storage();
-        for i in 0..min(storage1.len(), storage2.len()) {
-            d += (storage1[i] ^ storage2[i]).count_ones() as usize;
+    pub fn hamming_distance(&self, other: BitCodeRef) -> usize {
+        hamming_distance(self.blocks, other.blocks)
+    }
+
+    /// Returns the Hamming distance to the other code if it is <= radius, and None otherwise. See BitCode::within.
+    #[inline]
+    pub fn within(&self, other: BitCodeRef, radius: usize) -> Option<usize> {
+        within(self.blocks, other.blocks, radius)
+    }
+
+    /// Returns the Hamming distance between the codes, or an error if they are not the same length.
+    #[inline]
+    pub fn try_hamming_distance(&self, other: BitCodeRef) -> Result<usize, BitCodeError> {
+        if self.len() != other.len() {
+            return Err(BitCodeError::LengthMismatch { left: self.len(), right: other.len() });
         }
-        d
+        Ok(self.hamming_distance(other))
+    }
+
+    /// Estimates the cosine similarity of the feature vectors the codes were projected from. See BitCode::estimated_cosine.
+    pub fn estimated_cosine(&self, other: BitCodeRef) -> CosineEstimate {
+        estimated_cosine(self.hamming_distance(other), max(self.len(), other.len()))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.num_bits == 0
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.bits.len()
+        self.num_bits
     }
 
-    pub fn index_values(&self, mut index_length: usize) -> Vec<BitVec> {
+    /// Returns the substrings used as multi-index keys: the code split into consecutive runs of index_length bits (clamped to 1..=64 and to the code length), each packed into a u64 as by substring. The last substring is padded with unset bits.
+    pub fn index_values(&self, mut index_length: usize) -> Vec<u64> {
         let num_bits = self.len();
         // Ensure bits per index is within required range.
-        if index_length < 1 { index_length = 1; };
+        if index_length > 64 { index_length = 64; };
         if index_length > num_bits { index_length = num_bits; };
+        if index_length < 1 { index_length = 1; };
         // Compute number of indexes required.
         let num_indexes = get_num_indexes(num_bits, index_length);
-        // Set index values.
-        let mut index_values = Vec::new();
-        for i in 0..num_indexes {
-            let mut index_value = BitVec::from_elem(index_length, false);
-            for j in (i * index_length)..((i + 1) * index_length) {
-                if j < num_bits {
-                    index_value.set(j - (i * index_length), self.get(j).unwrap());
+        (0..num_indexes).map(|i| self.substring(i * index_length, index_length)).collect()
+    }
+
+    /// Returns the length bits (at most 64) starting at bit start, packed into a u64 so that bit start is the lowest bit. Bits beyond the end of the code are unset.
+    #[inline]
+    pub fn substring(&self, start: usize, length: usize) -> u64 {
+        assert!(length <= 64, "substrings are at most 64 bits long, not {}", length);
+        let block = start / 64;
+        let offset = start % 64;
+        if length == 0 || block >= self.blocks.len() { return 0; }
+        let mut value = self.blocks[block] >> offset;
+        if offset > 0 && offset + length > 64 && block + 1 < self.blocks.len() {
+            value |= self.blocks[block + 1] << (64 - offset);
+        }
+        if length < 64 { value &= (1u64 << length) - 1; }
+        value
+    }
+
+    /// Returns an iterator over the positions of set bits, in ascending order.
+    pub fn ones(&self) -> Ones<'a> {
+        Ones::new(self.blocks)
+    }
+
+    /// Returns an owned copy of the code.
+    pub fn to_bit_code(&self) -> BitCode {
+        BitCode{ blocks: self.blocks.to_vec(), num_bits: self.num_bits }
+    }
+
+    fn bit_string(&self) -> String {
+        (0..self.num_bits).map(|i| if self.get(i) == Some(true) { '1' } else { '0' }).collect()
+    }
+}
+
+
+impl<'a> fmt::Debug for BitCodeRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BitCodeRef {{ bits: {} }}", self.bit_string())
+    }
+}
+
+
+impl<'a> From<&'a BitCode> for BitCodeRef<'a> {
+    fn from(bit_code: &'a BitCode) -> BitCodeRef<'a> {
+        bit_code.view()
+    }
+}
+
+
+// Hamming distance between packed blocks, treating the shorter code as padded with unset bits.
+#[inline]
+pub(crate) fn hamming_distance(blocks1: &[u64], blocks2: &[u64]) -> usize {
+    let mut d: usize = 0;
+    let n = min(blocks1.len(), blocks2.len());
+    for i in 0..n {
+        d += (blocks1[i] ^ blocks2[i]).count_ones() as usize;
+    }
+    // Bits past the end of the shorter code are unset; unused bits in a partial last block are always unset.
+    let longer = if blocks1.len() > n { blocks1 } else { blocks2 };
+    for block in &longer[n..] {
+        d += block.count_ones() as usize;
+    }
+    d
+}
+
+
+// Hamming distance between packed blocks if it is <= radius, stopping as soon as the radius is exceeded.
+#[inline]
+pub(crate) fn within(blocks1: &[u64], blocks2: &[u64], radius: usize) -> Option<usize> {
+    let mut d: usize = 0;
+    let n = min(blocks1.len(), blocks2.len());
+    for i in 0..n {
+        d += (blocks1[i] ^ blocks2[i]).count_ones() as usize;
+        if d > radius { return None; }
+    }
+    let longer = if blocks1.len() > n { blocks1 } else { blocks2 };
+    for block in &longer[n..] {
+        d += block.count_ones() as usize;
+        if d > radius { return None; }
+    }
+    Some(d)
+}
+
+
+#[inline]
+fn assert_same_len(a: &BitCode, b: &BitCode) {
+    assert!(a.len() == b.len(), "bit codes have different lengths ({} and {})", a.len(), b.len());
+}
+
+
+// Assign operators work block-wise; binary operators are implemented for owned and borrowed operands in terms of them.
+macro_rules! impl_binary_op {
+    ($op:ident, $method:ident, $assign_op:ident, $assign_method:ident, $operator:tt) => {
+        impl<'a> $assign_op<&'a BitCode> for BitCode {
+            fn $assign_method(&mut self, other: &'a BitCode) {
+                assert_same_len(self, other);
+                for (block, other_block) in self.blocks.iter_mut().zip(&other.blocks) {
+                    *block = *block $operator *other_block;
                 }
             }
-            index_values.push(index_value)
         }
-        index_values
+
+        impl<'a, 'b> $op<&'b BitCode> for &'a BitCode {
+            type Output = BitCode;
+            fn $method(self, other: &'b BitCode) -> BitCode {
+                let mut result = self.clone();
+                result.$assign_method(other);
+                result
+            }
+        }
+
+        impl<'b> $op<&'b BitCode> for BitCode {
+            type Output = BitCode;
+            fn $method(mut self, other: &'b BitCode) -> BitCode {
+                self.$assign_method(other);
+                self
+            }
+        }
+
+        impl $op<BitCode> for BitCode {
+            type Output = BitCode;
+            fn $method(mut self, other: BitCode) -> BitCode {
+                self.$assign_method(&other);
+                self
+            }
+        }
+    };
+}
+
+impl_binary_op!(BitAnd, bitand, BitAndAssign, bitand_assign, &);
+impl_binary_op!(BitOr, bitor, BitOrAssign, bitor_assign, |);
+impl_binary_op!(BitXor, bitxor, BitXorAssign, bitxor_assign, ^);
+
+
+impl Not for BitCode {
+    type Output = BitCode;
+    fn not(mut self) -> BitCode {
+        for block in self.blocks.iter_mut() { *block = !*block; }
+        self.fix_last_block();
+        self
     }
+}
 
-    #[inline]
-    pub fn set(&mut self, bit_number: usize, value: bool) {
-        self.bits.set(bit_number, value);
+
+impl Not for &BitCode {
+    type Output = BitCode;
+    fn not(self) -> BitCode {
+        !self.clone()
+    }
+}
+
+
+/// Iterator over the positions of set bits in packed u64 blocks.
+pub struct Ones<'a> {
+    blocks: &'a [u64],
+    block: usize,
+    offset: usize,
+    current: u64,
+}
+
+
+impl<'a> Ones<'a> {
+    pub(crate) fn new(blocks: &'a [u64]) -> Self {
+        Ones { blocks, block: 0, offset: 0, current: 0 }
+    }
+}
+
+
+impl<'a> Iterator for Ones<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.current == 0 {
+            if self.block >= self.blocks.len() { return None; }
+            self.current = self.blocks[self.block];
+            self.offset = self.block * 64;
+            self.block += 1;
+        }
+        let position = self.offset + self.current.trailing_zeros() as usize;
+        // Clear the lowest set bit.
+        self.current &= self.current - 1;
+        Some(position)
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use bit_vec::BitVec;
+    use error::BitCodeError;
     use super::BitCode;
+    use test::Bencher;
     use utils::random_bit_string;
 
     #[test]
@@ -110,6 +455,33 @@ mod tests {
         assert_eq!(bc.len(), 100);
     }
 
+    #[test]
+    fn from_blocks() {
+        let bc = BitCode::from_blocks(vec![0b1011, !0], 70);
+        assert_eq!(bc.len(), 70);
+        assert_eq!(bc.count_ones(), 3 + 6);
+        assert_eq!(bc.get(3), Some(true));
+        assert_eq!(bc.get(69), Some(true));
+        assert_eq!(bc.get(70), None);
+        assert_eq!(bc.blocks(), &[0b1011, 0b111111]);
+        assert_eq!(format!("{:?}", BitCode::from_bit_string("0110")), "BitCode { bits: 0110 }");
+    }
+
+    #[test]
+    fn view() {
+        let bc1 = BitCode::from_bit_string(&random_bit_string(200));
+        let bc2 = BitCode::from_bit_string(&random_bit_string(200));
+        let (v1, v2) = (bc1.view(), bc2.view());
+        assert_eq!(v1.len(), 200);
+        assert_eq!(v1.count_ones(), bc1.count_ones());
+        assert_eq!(v1.hamming_distance(v2), bc1.hamming_distance(&bc2));
+        assert_eq!(v1.within(v2, 90), bc1.within(&bc2, 90));
+        assert_eq!(v1.diff_positions(v2), bc1.diff_positions(&bc2));
+        assert_eq!(v1.ones().collect::<Vec<usize>>(), bc1.ones().collect::<Vec<usize>>());
+        assert_eq!(v1.index_values(10), bc1.index_values(10));
+        assert_eq!(v1.to_bit_code(), bc1);
+    }
+
     #[test]
     fn set_get() {
         let mut bc = BitCode::new(512);
@@ -129,24 +501,36 @@ mod tests {
 
     #[test]
     fn index_values() {
-        // Test that index values are what they should be.
+        // Test that index values are what they should be; bit i of a substring is bit i of its key.
         let bools = vec![true, false, true, false, true, false, true, false, true, false, true, true];
         let bc = BitCode::from_bools(&bools);
-        let ivs1 = bc.index_values(4);
-        let ivs1_correct = vec![
-            BitVec::from_fn(4, |i| { vec![true, false, true, false][i] }),
-            BitVec::from_fn(4, |i| { vec![true, false, true, false][i] }),
-            BitVec::from_fn(4, |i| { vec![true, false, true, true][i] }),
-        ];
-        assert_eq!(ivs1.len(), ivs1_correct.len());
-        assert_eq!(ivs1, ivs1_correct);
-        let ivs2 = bc.index_values(10);
-        let ivs2_correct = vec![
-            BitVec::from_fn(10, |i| { vec![true, false, true, false, true, false, true, false, true, false][i] }),
-            BitVec::from_fn(10, |i| { vec![true, true, false, false, false, false, false, false, false, false][i] }),
-        ];
-        assert_eq!(ivs2.len(), ivs2_correct.len());
-        assert_eq!(ivs2, ivs2_correct);
+        assert_eq!(bc.index_values(4), vec![0b0101, 0b0101, 0b1101]);
+        assert_eq!(bc.index_values(10), vec![0b0101010101, 0b11]);
+        assert_eq!(bc.index_values(100), vec![0b110101010101]);
+        assert_eq!(bc.index_values(0).len(), 12);
+    }
+
+    #[test]
+    fn estimated_cosine() {
+        let a = BitCode::from_bit_string("00000000");
+        let b = BitCode::from_bit_string("11110000");
+        let estimate = a.estimated_cosine(&b);
+        assert!(estimate.estimate().abs() < 1e-12);
+        assert!(estimate.lower() < 0.0 && estimate.upper() > 0.0);
+        assert_eq!(a.estimated_cosine(&a).estimate(), 1.0);
+        assert_eq!(a.view().estimated_cosine(b.view()), estimate);
+    }
+
+    #[test]
+    fn substring() {
+        let bc = BitCode::from_bit_string(&random_bit_string(200));
+        for &(start, length) in &[(0, 64), (3, 7), (60, 10), (64, 64), (100, 64), (130, 64), (190, 20), (150, 0)] {
+            let mut expected: u64 = 0;
+            for j in 0..length {
+                if bc.get(start + j) == Some(true) { expected |= 1 << j; }
+            }
+            assert_eq!(bc.substring(start, length), expected);
+        }
     }
 
     #[test]
@@ -166,7 +550,7 @@ mod tests {
         for (i, b) in bools.iter().enumerate() {
             match bc.get(i) {
                 Some(x) => assert_eq!(&x, b),
-                None => assert!(false),
+                None => panic!("bit {} missing", i),
             }
         }
         assert_eq!(bc.hamming_distance(&bc), 0);
@@ -175,7 +559,7 @@ mod tests {
     #[test]
     fn bit_codes_are_equal() {
         let bc1 = BitCode::from_bit_string("010101010101");
-        let bc2 = BitCode::from_bools(&vec![false, true, false, true, false, true, false, true, false, true, false, true]);
+        let bc2 = BitCode::from_bools(&[false, true, false, true, false, true, false, true, false, true, false, true]);
         assert_eq!(bc1.hamming_distance(&bc2), 0);
     }
 
@@ -185,6 +569,89 @@ mod tests {
         assert_eq!(bc.len(), 256);
     }
 
+    #[test]
+    fn hamming_distance_partial_blocks() {
+        // Compare against a bit-by-bit count for lengths that do not fill the last block.
+        for &n in &[1, 31, 33, 63, 65, 100, 127, 129, 200] {
+            let bc1 = BitCode::from_bit_string(&random_bit_string(n));
+            let bc2 = BitCode::from_bit_string(&random_bit_string(n));
+            let d = (0..n).filter(|&i| bc1.get(i) != bc2.get(i)).count();
+            assert_eq!(bc1.hamming_distance(&bc2), d);
+            assert_eq!(bc1.try_hamming_distance(&bc2), Ok(d));
+            assert_eq!((!&bc1).hamming_distance(&bc1), n);
+        }
+    }
+
+    #[test]
+    fn hamming_distance_different_lengths() {
+        // The shorter code is treated as padded with unset bits.
+        let bc1 = BitCode::from_bit_string("1010");
+        let bc2 = BitCode::from_bit_string(&format!("1011{}", "1".repeat(96)));
+        assert_eq!(bc1.hamming_distance(&bc2), 97);
+        assert_eq!(bc2.hamming_distance(&bc1), 97);
+        let bc3 = BitCode::from_bit_string(&format!("1010{}", "0".repeat(96)));
+        assert_eq!(bc1.hamming_distance(&bc3), 0);
+        // The checked version rejects them.
+        assert_eq!(bc1.try_hamming_distance(&bc2), Err(BitCodeError::LengthMismatch { left: 4, right: 100 }));
+        assert_eq!(bc2.try_hamming_distance(&bc1), Err(BitCodeError::LengthMismatch { left: 100, right: 4 }));
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let bc1 = BitCode::from_bit_string("1100110011");
+        let bc2 = BitCode::from_bit_string("1010101010");
+        assert_eq!(&bc1 & &bc2, BitCode::from_bit_string("1000100010"));
+        assert_eq!(&bc1 | &bc2, BitCode::from_bit_string("1110111011"));
+        assert_eq!(&bc1 ^ &bc2, BitCode::from_bit_string("0110011001"));
+        assert_eq!(!&bc1, BitCode::from_bit_string("0011001100"));
+        assert_eq!((!&bc1).len(), 10);
+        assert_eq!((!&bc1).count_ones(), 4);
+        let mut bc3 = bc1.clone();
+        bc3 ^= &bc2;
+        bc3 ^= &bc2;
+        assert_eq!(bc3, bc1);
+        bc3 &= &bc2;
+        assert_eq!(bc3, bc1.clone() & bc2.clone());
+        bc3 |= &bc1;
+        assert_eq!(bc3, bc1);
+        // XOR of random codes agrees with Hamming distance.
+        let bc4 = BitCode::from_bit_string(&random_bit_string(300));
+        let bc5 = BitCode::from_bit_string(&random_bit_string(300));
+        assert_eq!((&bc4 ^ &bc5).count_ones(), bc4.hamming_distance(&bc5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bitwise_operators_check_length() {
+        let _ = &BitCode::new(10) & &BitCode::new(11);
+    }
+
+    #[test]
+    fn ones_and_diff_positions() {
+        let mut bc1 = BitCode::new(100);
+        for i in &[0, 31, 32, 63, 64, 99] { bc1.set(*i, true); }
+        assert_eq!(bc1.ones().collect::<Vec<usize>>(), vec![0, 31, 32, 63, 64, 99]);
+        assert_eq!(BitCode::new(100).ones().count(), 0);
+        let mut bc2 = bc1.clone();
+        bc2.set(31, false);
+        bc2.set(50, true);
+        assert_eq!(bc1.diff_positions(&bc2), vec![31, 50]);
+        assert_eq!(bc1.diff_positions(&bc1), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn majority() {
+        let bit_codes = vec![
+            BitCode::from_bit_string("1100"),
+            BitCode::from_bit_string("1010"),
+            BitCode::from_bit_string("1001"),
+        ];
+        assert_eq!(BitCode::majority(&bit_codes), Some(BitCode::from_bit_string("1000")));
+        // Ties are unset.
+        assert_eq!(BitCode::majority(&bit_codes[..2]), Some(BitCode::from_bit_string("1000")));
+        assert_eq!(BitCode::majority(&[]), None);
+    }
+
     #[test]
     fn hamming_distance() {
         let bc1 = BitCode::from_bit_string("010101010101");
@@ -193,4 +660,46 @@ mod tests {
         assert_eq!(bc2.hamming_distance(&bc2), 0);
         assert_eq!(bc1.hamming_distance(&bc2), 12);
     }
+
+    #[test]
+    fn within() {
+        for &n in &[12, 100, 256, 1024] {
+            let bc1 = BitCode::from_bit_string(&random_bit_string(n));
+            let bc2 = BitCode::from_bit_string(&random_bit_string(n));
+            let d = bc1.hamming_distance(&bc2);
+            assert_eq!(bc1.within(&bc2, d), Some(d));
+            assert_eq!(bc1.within(&bc2, n), Some(d));
+            assert_eq!(bc1.within(&bc1, 0), Some(0));
+            if d > 0 { assert_eq!(bc1.within(&bc2, d - 1), None); }
+        }
+        // Codes of different lengths follow the same padding rule as hamming_distance.
+        let bc1 = BitCode::from_bit_string("1010");
+        let bc2 = BitCode::from_bit_string(&format!("1010{}", "1".repeat(60)));
+        assert_eq!(bc1.within(&bc2, 60), Some(60));
+        assert_eq!(bc1.within(&bc2, 59), None);
+    }
+
+    fn bench_hamming_distance(b: &mut Bencher, num_bits: usize) {
+        let bc1 = BitCode::from_bit_string(&random_bit_string(num_bits));
+        let bc2 = BitCode::from_bit_string(&random_bit_string(num_bits));
+        b.iter(|| bc1.hamming_distance(&bc2) <= num_bits / 8);
+    }
+
+    fn bench_within(b: &mut Bencher, num_bits: usize) {
+        let bc1 = BitCode::from_bit_string(&random_bit_string(num_bits));
+        let bc2 = BitCode::from_bit_string(&random_bit_string(num_bits));
+        b.iter(|| bc1.within(&bc2, num_bits / 8));
+    }
+
+    #[bench]
+    fn hamming_distance_256(b: &mut Bencher) { bench_hamming_distance(b, 256); }
+
+    #[bench]
+    fn hamming_distance_1024(b: &mut Bencher) { bench_hamming_distance(b, 1024); }
+
+    #[bench]
+    fn within_256(b: &mut Bencher) { bench_within(b, 256); }
+
+    #[bench]
+    fn within_1024(b: &mut Bencher) { bench_within(b, 1024); }
 }
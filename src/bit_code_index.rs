@@ -1,64 +1,533 @@
-use bit_vec::BitVec;
-use std::collections::{HashMap, HashSet};
-use utils::FastHasher;
+use bit_code::BitCodeRef;
+use rand::{Rng, SeedableRng};
+use rand::isaac::Isaac64Rng;
+use std::cmp::min;
+use std::fmt;
+use std::mem::size_of;
+use utils::get_num_indexes;
 
 
+// Multi-index over bit codes. Each code is split into substrings as given by a partition of its bits, and each substring has a table mapping substring values (as u64 keys) to the codes having them.
 #[derive(Debug)]
 pub struct BitCodeIndex {
-    index_length: usize,
-    indexes: Vec<HashMap<BitVec, HashSet<usize, FastHasher>, FastHasher>>,
+    partition: Partition,
+    tables: Vec<SubstringTable>,
+    num_values: usize,
+    expected_candidates: f64,               // Expected candidates per exact lookup, summed over tables.
+}
+
+
+// Assignment of the bits of a code to disjoint substrings of at most 64 bits, one per table. Bit j of a substring's key is bit positions[j] of the code.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Partition {
+    substrings: Vec<Substring>,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Substring {
+    positions: Vec<usize>,                  // Bit positions in the code, in ascending order.
+    contiguous: bool,                       // Whether positions is a single run, so the key can be read directly from the blocks.
+}
+
+
+impl Substring {
+    fn new(mut positions: Vec<usize>) -> Self {
+        positions.sort_unstable();
+        let contiguous = positions.windows(2).all(|w| w[1] == w[0] + 1);
+        Substring { positions, contiguous }
+    }
+
+    #[inline]
+    fn key(&self, code: BitCodeRef) -> u64 {
+        if self.contiguous {
+            return code.substring(self.positions[0], self.positions.len());
+        }
+        let blocks = code.blocks();
+        let mut key: u64 = 0;
+        for (j, &position) in self.positions.iter().enumerate() {
+            // Positions beyond a shorter code read as 0, as if it were zero-padded.
+            key |= blocks.get(position / 64).map_or(0, |block| (block >> (position % 64)) & 1) << j;
+        }
+        key
+    }
+}
+
+
+impl Partition {
+    // Consecutive runs of bits_per_index bits, the last of which may be shorter.
+    pub fn contiguous(num_bits: usize, bits_per_index: usize) -> Self {
+        let num_indexes = get_num_indexes(num_bits, bits_per_index);
+        Partition::from_sizes((0..num_bits).collect(), &vec![bits_per_index; num_indexes])
+    }
+
+    // Consecutive runs of bits, as many as contiguous would give, with lengths differing by at most one bit.
+    pub fn balanced(num_bits: usize, bits_per_index: usize) -> Self {
+        Partition::from_order((0..num_bits).collect(), bits_per_index)
+    }
+
+    // Balanced substrings of bits chosen by a random permutation, so neighbouring bits are unlikely to share a table. The same seed gives the same partition.
+    pub fn random(num_bits: usize, bits_per_index: usize, seed: u64) -> Self {
+        let mut order: Vec<usize> = (0..num_bits).collect();
+        Isaac64Rng::from_seed(&[seed]).shuffle(&mut order);
+        Partition::from_order(order, bits_per_index)
+    }
+
+    // Balanced substrings, chosen greedily so that bits that are correlated in the sample codes are placed in different tables.
+    pub fn decorrelated<'a, I: Iterator<Item=BitCodeRef<'a>>>(sample: I, num_bits: usize, bits_per_index: usize) -> Self {
+        let correlations = bit_correlations(sample, num_bits);
+        let sizes = balanced_sizes(num_bits, bits_per_index);
+        let mut substrings: Vec<Vec<usize>> = vec![Vec::new(); sizes.len()];
+        for bit in 0..num_bits {
+            // Place the bit in the table with the least total correlation to it, preferring emptier tables on ties.
+            let mut best: Option<(f64, usize, usize)> = None;
+            for (t, substring) in substrings.iter().enumerate() {
+                if substring.len() == sizes[t] { continue; }
+                let cost: f64 = substring.iter().map(|&other| correlations[bit * num_bits + other].abs()).sum();
+                let candidate = (cost, substring.len(), t);
+                if best.is_none_or(|b| candidate < b) { best = Some(candidate); }
+            }
+            substrings[best.unwrap().2].push(bit);
+        }
+        Partition { substrings: substrings.into_iter().map(Substring::new).collect() }
+    }
+
+    // Splits order into balanced runs, each of which becomes a substring.
+    fn from_order(order: Vec<usize>, bits_per_index: usize) -> Self {
+        let sizes = balanced_sizes(order.len(), bits_per_index);
+        Partition::from_sizes(order, &sizes)
+    }
+
+    // Splits order into runs of the given sizes; the last run is truncated at the end of order.
+    fn from_sizes(order: Vec<usize>, sizes: &[usize]) -> Self {
+        let mut substrings: Vec<Substring> = Vec::with_capacity(sizes.len());
+        let mut start = 0;
+        for &size in sizes {
+            let end = if start + size < order.len() { start + size } else { order.len() };
+            substrings.push(Substring::new(order[start..end].to_vec()));
+            start = end;
+        }
+        Partition { substrings }
+    }
+
+    // Returns the key of each substring of the code.
+    pub fn keys(&self, code: BitCodeRef) -> Vec<u64> {
+        self.substrings.iter().map(|substring| substring.key(code)).collect()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.substrings.len()
+    }
+
+    // Length of the longest substring.
+    pub fn max_substring_length(&self) -> usize {
+        self.substrings.iter().map(|substring| substring.positions.len()).max().unwrap_or(0)
+    }
+}
+
+
+// Lengths of the substrings a balanced partition of num_bits bits has: as many as a contiguous partition, differing by at most one.
+fn balanced_sizes(num_bits: usize, bits_per_index: usize) -> Vec<usize> {
+    let num_indexes = get_num_indexes(num_bits, bits_per_index);
+    (0..num_indexes).map(|i| num_bits / num_indexes + if i < num_bits % num_indexes { 1 } else { 0 }).collect()
+}
+
+
+// Returns the num_bits x num_bits matrix of (phi) correlations between bits over the sample codes, in row-major order. Bits that are constant in the sample have zero correlation with every bit.
+fn bit_correlations<'a, I: Iterator<Item=BitCodeRef<'a>>>(sample: I, num_bits: usize) -> Vec<f64> {
+    let mut counts: Vec<f64> = vec![0.0; num_bits];
+    let mut co_counts: Vec<f64> = vec![0.0; num_bits * num_bits];
+    let mut n = 0.0;
+    let mut ones: Vec<usize> = Vec::new();
+    for code in sample {
+        ones.clear();
+        ones.extend(code.ones().filter(|&i| i < num_bits));
+        for &i in &ones {
+            counts[i] += 1.0;
+            for &j in &ones { co_counts[i * num_bits + j] += 1.0; }
+        }
+        n += 1.0;
+    }
+    let mut correlations: Vec<f64> = vec![0.0; num_bits * num_bits];
+    for i in 0..num_bits {
+        for j in 0..num_bits {
+            let denominator = (counts[i] * (n - counts[i]) * counts[j] * (n - counts[j])).sqrt();
+            if denominator > 0.0 {
+                correlations[i * num_bits + j] = (n * co_counts[i * num_bits + j] - counts[i] * counts[j]) / denominator;
+            }
+        }
+    }
+    correlations
+}
+
+
+// Table of codes keyed by one substring, stored in compressed sparse row form: keys are sorted and unique, and the codes with keys[k] are values[offsets[k]..offsets[k + 1]], in ascending order.
+#[derive(Debug, Default)]
+struct SubstringTable {
+    keys: Vec<u64>,
+    offsets: Vec<u32>,
+    values: Vec<u32>,
+}
+
+
+impl SubstringTable {
+    // Build a table from (key, value) pairs, which are sorted in place.
+    fn from_pairs(pairs: &mut [(u64, u32)]) -> Self {
+        pairs.sort_unstable();
+        let mut table = SubstringTable::default();
+        table.values.reserve_exact(pairs.len());
+        for (i, &(key, value)) in pairs.iter().enumerate() {
+            if i == 0 || pairs[i - 1].0 != key {
+                table.keys.push(key);
+                table.offsets.push(i as u32);
+            }
+            table.values.push(value);
+        }
+        table.offsets.push(pairs.len() as u32);
+        table.keys.shrink_to_fit();
+        table.offsets.shrink_to_fit();
+        table
+    }
+
+    // Codes stored under key, in ascending order.
+    #[inline]
+    fn get(&self, key: u64) -> &[u32] {
+        match self.keys.binary_search(&key) {
+            Ok(k) => &self.values[(self.offsets[k] as usize)..(self.offsets[k + 1] as usize)],
+            Err(_) => &[],
+        }
+    }
+
+    // Expected size of the bucket of a code drawn from the table, i.e. the sum of squared bucket sizes over the number of codes.
+    fn expected_candidates(&self) -> f64 {
+        if self.values.is_empty() { return 0.0; }
+        let sum_squares: f64 = self.offsets.windows(2).map(|w| ((w[1] - w[0]) as f64).powi(2)).sum();
+        sum_squares / self.values.len() as f64
+    }
+
+    fn stats(&self, substring_length: usize) -> TableStats {
+        let mut sizes: Vec<usize> = self.offsets.windows(2).map(|w| (w[1] - w[0]) as usize).collect();
+        sizes.sort_unstable();
+        let num_values = self.values.len();
+        let num_buckets = sizes.len();
+        TableStats {
+            substring_length,
+            num_buckets,
+            max_bucket_size: sizes.last().cloned().unwrap_or(0),
+            mean_bucket_size: if num_buckets > 0 { num_values as f64 / num_buckets as f64 } else { 0.0 },
+            p99_bucket_size: if num_buckets > 0 { sizes[(num_buckets * 99).div_ceil(100) - 1] } else { 0 },
+            empty_key_ratio: 1.0 - num_buckets as f64 / 2f64.powi(substring_length as i32),
+            memory_bytes: self.keys.capacity() * size_of::<u64>() + (self.offsets.capacity() + self.values.capacity()) * size_of::<u32>(),
+            expected_candidates: self.expected_candidates(),
+        }
+    }
+}
+
+
+/// Statistics describing a multi-index, for tuning substring length and spotting degenerate bits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexStats {
+    num_values: usize,
+    tables: Vec<TableStats>,
+}
+
+
+impl IndexStats {
+    /// Number of codes in the index.
+    pub fn num_values(&self) -> usize { self.num_values }
+
+    /// Statistics for each table, one per substring.
+    pub fn tables(&self) -> &[TableStats] { &self.tables }
+
+    /// Approximate heap memory used by the tables, in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.tables.iter().map(|table| table.memory_bytes).sum()
+    }
+
+    /// Expected number of candidates an exact-substring lookup returns for a query drawn from the indexed codes, summed over tables. Codes found in several tables are counted once per table, so this bounds the distances computed by a search with radius below the number of tables.
+    pub fn estimated_candidates_per_query(&self) -> f64 {
+        self.tables.iter().map(|table| table.expected_candidates).sum()
+    }
+}
+
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} codes in {} tables, {} bytes, ~{:.1} candidates per query", self.num_values, self.tables.len(), self.memory_bytes(), self.estimated_candidates_per_query())?;
+        for (i, table) in self.tables.iter().enumerate() {
+            writeln!(f, "  table {}: {} bits, {} buckets, bucket size max {} mean {:.2} p99 {}, {:.4} of keys empty, ~{:.1} candidates per query",
+                i, table.substring_length, table.num_buckets, table.max_bucket_size, table.mean_bucket_size, table.p99_bucket_size, table.empty_key_ratio, table.expected_candidates)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Statistics describing one table of a multi-index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableStats {
+    substring_length: usize,
+    num_buckets: usize,
+    max_bucket_size: usize,
+    mean_bucket_size: f64,
+    p99_bucket_size: usize,
+    empty_key_ratio: f64,
+    memory_bytes: usize,
+    expected_candidates: f64,
+}
+
+
+impl TableStats {
+    /// Number of bits in the substring keying the table.
+    pub fn substring_length(&self) -> usize { self.substring_length }
+
+    /// Number of distinct substring values with at least one code.
+    pub fn num_buckets(&self) -> usize { self.num_buckets }
+
+    /// Number of codes in the largest bucket.
+    pub fn max_bucket_size(&self) -> usize { self.max_bucket_size }
+
+    /// Mean number of codes per non-empty bucket.
+    pub fn mean_bucket_size(&self) -> f64 { self.mean_bucket_size }
+
+    /// 99th percentile of the number of codes per non-empty bucket.
+    pub fn p99_bucket_size(&self) -> usize { self.p99_bucket_size }
+
+    /// Fraction of the possible substring values with no codes. A ratio near 1 with large buckets suggests bits that are nearly constant or strongly correlated.
+    pub fn empty_key_ratio(&self) -> f64 { self.empty_key_ratio }
+
+    /// Approximate heap memory used by the table, in bytes.
+    pub fn memory_bytes(&self) -> usize { self.memory_bytes }
+
+    /// Expected size of the bucket an indexed code falls into, i.e. the candidates an exact lookup in this table returns for a query drawn from the indexed codes.
+    pub fn expected_candidates(&self) -> f64 { self.expected_candidates }
 }
 
 
 impl BitCodeIndex {
     pub fn new() -> Self {
-        BitCodeIndex { index_length: 0, indexes: Vec::new() }
+        BitCodeIndex { partition: Partition::default(), tables: Vec::new(), num_values: 0, expected_candidates: 0.0 }
     }
 
-    // Add index values to the index.
-    pub fn add(&mut self, index_values: &Vec<BitVec>, value: usize) {
-        for (i, bv) in index_values.iter().enumerate() {
-            if !self.indexes[i].contains_key(bv) {
-                let hashset: HashSet<usize, FastHasher> = HashSet::default();
-                self.indexes[i].insert(bv.clone(), hashset);
+    // Index codes 0..num_values, where code(i) returns the ith code, on the substrings given by the partition. Any existing contents are replaced.
+    pub fn build<'a, F: Fn(usize) -> BitCodeRef<'a>>(&mut self, partition: Partition, num_values: usize, code: F) {
+        assert!(partition.max_substring_length() <= 64, "substrings must be at most 64 bits long");
+        assert!(num_values <= u32::MAX as usize, "too many codes to index ({})", num_values);
+        self.num_values = num_values;
+        self.tables = Vec::with_capacity(partition.len());
+        let mut pairs: Vec<(u64, u32)> = Vec::with_capacity(num_values);
+        for substring in &partition.substrings {
+            pairs.clear();
+            for value in 0..num_values {
+                pairs.push((substring.key(code(value)), value as u32));
             }
-            self.indexes[i].get_mut(bv).unwrap().insert(value);
+            self.tables.push(SubstringTable::from_pairs(&mut pairs));
         }
+        self.expected_candidates = self.tables.iter().map(|table| table.expected_candidates()).sum();
+        self.partition = partition;
     }
 
-    pub fn index_length(&self) -> usize {
-        self.index_length
+    // Returns the keys of the code's substrings, for looking it up in the index.
+    pub fn keys(&self, code: BitCodeRef) -> Vec<u64> {
+        self.partition.keys(code)
     }
 
-    pub fn candidate_indices(&self, needle_index_values: &Vec<BitVec>) -> HashSet<usize, FastHasher> {
-        let mut candidates: HashSet<usize, FastHasher> = HashSet::default();
-        for i in 0..self.len() {
-            match self.indexes[i].get(&needle_index_values[i]) {
-                Some(new_candidates) => {
-                    for entry in new_candidates { candidates.insert(*entry); }
-                },
-                None => (),
-            }
+    // Length of the longest substring, and so the largest useful substring distance.
+    pub fn max_substring_length(&self) -> usize {
+        self.partition.max_substring_length()
+    }
+
+    // Call f with every value stored under any of the needle's index values, without allocating. A value is passed once for each table it matches in, so callers computing distances should deduplicate first.
+    pub fn for_each_candidate<F: FnMut(usize)>(&self, needle_index_values: &[u64], mut f: F) {
+        for (table, &key) in self.tables.iter().zip(needle_index_values) {
+            for &value in table.get(key) { f(value as usize); }
         }
-        candidates
     }
 
-    pub fn init(&mut self, index_length: usize, num_indexes: usize) {
-        self.index_length = index_length;
-        self.indexes = Vec::with_capacity(num_indexes);
-        for _ in 0..num_indexes {
-            let hashmap: HashMap<BitVec, HashSet<usize, FastHasher>, FastHasher> = HashMap::default();
-            self.indexes.push(hashmap);
+    // Call f with every value stored under a key that differs from the needle's index value in exactly substring_distance bits, for each index. A value may be passed more than once.
+    pub fn for_each_candidate_at_distance<F: FnMut(usize)>(&self, needle_index_values: &[u64], substring_distance: usize, mut f: F) {
+        for ((table, substring), &key) in self.tables.iter().zip(&self.partition.substrings).zip(needle_index_values) {
+            for_each_neighbor(key, substring.positions.len(), 0, substring_distance, &mut |key| {
+                for &value in table.get(key) { f(value as usize); }
+            });
         }
     }
 
+    // Number of keys for_each_candidate_at_distance probes at the substring distance, saturating at usize::MAX.
+    pub fn num_keys_at_distance(&self, substring_distance: usize) -> usize {
+        self.partition.substrings.iter().fold(0usize, |n, substring| n.saturating_add(binomial(substring.positions.len(), substring_distance)))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
-        self.indexes.len()
+        self.tables.len()
     }
 
     #[inline]
     pub fn max_searchable_radius(&self) -> usize {
-        self.len() - 1
+        self.len().saturating_sub(1)
+    }
+
+    // Returns statistics on the tables' buckets.
+    pub fn stats(&self) -> IndexStats {
+        let tables = self.tables.iter().zip(&self.partition.substrings).map(|(table, substring)| table.stats(substring.positions.len())).collect();
+        IndexStats { num_values: self.num_values, tables }
+    }
+
+    // Expected number of candidates an exact lookup returns for a query drawn from the indexed codes, summed over tables, as IndexStats::estimated_candidates_per_query.
+    #[inline]
+    pub fn expected_candidates(&self) -> f64 {
+        self.expected_candidates
+    }
+
+    // Number of values indexed by the last build.
+    #[inline]
+    pub fn num_values(&self) -> usize {
+        self.num_values
+    }
+}
+
+
+// Number of ways of choosing k of n things, saturating at usize::MAX.
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n { return 0; }
+    let k = min(k, n - k);
+    let mut c: u128 = 1;
+    for i in 0..k {
+        c = c * (n - i) as u128 / (i + 1) as u128;
+        if c > usize::MAX as u128 { return usize::MAX; }
+    }
+    c as usize
+}
+
+
+// Call f with every key obtained by flipping `remaining` of the low key_length bits of key at positions >= start.
+fn for_each_neighbor<F: FnMut(u64)>(key: u64, key_length: usize, start: usize, remaining: usize, f: &mut F) {
+    if remaining == 0 {
+        f(key);
+        return;
+    }
+    for i in start..key_length {
+        if key_length - i < remaining { break; }
+        for_each_neighbor(key ^ (1u64 << i), key_length, i + 1, remaining - 1, f);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code::BitCode;
+    use super::{balanced_sizes, binomial, for_each_neighbor, BitCodeIndex, Partition};
+
+    #[test]
+    fn build_and_lookup() {
+        let bit_codes: Vec<BitCode> = ["110010", "110011", "000011", "110010"].iter().map(|s| BitCode::from_bit_string(s)).collect();
+        let mut index = BitCodeIndex::new();
+        index.build(Partition::contiguous(6, 4), bit_codes.len(), |i| bit_codes[i].view());
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.num_values(), 4);
+        assert_eq!(index.max_substring_length(), 4);
+        // The first substrings are 1100, 1100, 0000, 1100 and the second 10, 11, 11, 10.
+        assert_eq!(index.tables[0].keys, vec![0b0000, 0b0011]);
+        assert_eq!(index.tables[0].offsets, vec![0, 1, 4]);
+        assert_eq!(index.tables[0].values, vec![2, 0, 1, 3]);
+        assert_eq!(index.keys(bit_codes[0].view()), bit_codes[0].index_values(4));
+        let mut found: Vec<usize> = Vec::new();
+        index.for_each_candidate(&index.keys(bit_codes[0].view()), |value| found.push(value));
+        found.sort();
+        assert_eq!(found, vec![0, 0, 1, 3, 3]);
+        found.clear();
+        index.for_each_candidate(&[0b1111, 0b00], |value| found.push(value));
+        assert!(found.is_empty());
+        index.for_each_candidate_at_distance(&index.keys(bit_codes[2].view()), 1, |value| found.push(value));
+        found.sort();
+        assert_eq!(found, vec![0, 3]);
+    }
+
+    #[test]
+    fn stats() {
+        let bit_codes: Vec<BitCode> = ["110010", "110011", "000011", "110010"].iter().map(|s| BitCode::from_bit_string(s)).collect();
+        let mut index = BitCodeIndex::new();
+        index.build(Partition::contiguous(6, 4), bit_codes.len(), |i| bit_codes[i].view());
+        let stats = index.stats();
+        assert_eq!(stats.num_values(), 4);
+        assert_eq!(stats.tables().len(), 2);
+        // Buckets of sizes 1 and 3 among 16 keys, and 2 and 2 among 4 keys.
+        let table = &stats.tables()[0];
+        assert_eq!(table.substring_length(), 4);
+        assert_eq!(table.num_buckets(), 2);
+        assert_eq!(table.max_bucket_size(), 3);
+        assert_eq!(table.mean_bucket_size(), 2.0);
+        assert_eq!(table.p99_bucket_size(), 3);
+        assert_eq!(table.empty_key_ratio(), 14.0 / 16.0);
+        assert_eq!(table.expected_candidates(), 10.0 / 4.0);
+        assert_eq!(stats.tables()[1].empty_key_ratio(), 0.5);
+        assert_eq!(stats.estimated_candidates_per_query(), 2.5 + 2.0);
+        assert!(stats.memory_bytes() >= 2 * 4 * 4);
+        assert_eq!(BitCodeIndex::new().stats().tables().len(), 0);
+        assert!(format!("{}", stats).starts_with("4 codes in 2 tables"));
+    }
+
+    #[test]
+    fn partitions() {
+        assert_eq!(balanced_sizes(10, 4), vec![4, 3, 3]);
+        assert_eq!(balanced_sizes(12, 4), vec![4, 4, 4]);
+        let contiguous = Partition::contiguous(10, 4);
+        let sizes: Vec<usize> = contiguous.substrings.iter().map(|s| s.positions.len()).collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+        let balanced = Partition::balanced(10, 4);
+        assert_eq!(balanced.substrings[1].positions, vec![4, 5, 6]);
+        assert!(balanced.substrings.iter().all(|s| s.contiguous));
+        // Random partitions are balanced, cover every bit once and depend only on the seed.
+        let random = Partition::random(100, 8, 7);
+        assert_eq!(random, Partition::random(100, 8, 7));
+        assert_ne!(random, Partition::random(100, 8, 8));
+        assert_eq!(random.len(), 13);
+        let mut positions: Vec<usize> = random.substrings.iter().flat_map(|s| s.positions.clone()).collect();
+        positions.sort();
+        assert_eq!(positions, (0..100).collect::<Vec<usize>>());
+        // Keys gather the substring's bits whether or not they are contiguous.
+        let bit_code = BitCode::from_bit_string("0110100111");
+        let gathered = Partition::from_sizes(vec![9, 0, 2, 5, 1, 3, 4, 6, 7, 8], &[4, 6]);
+        assert_eq!(gathered.keys(bit_code.view()), vec![0b1010, 0b110101]);
+    }
+
+    #[test]
+    fn decorrelated_partition() {
+        // Bits 2k and 2k + 1 are always equal, so a decorrelated partition separates them.
+        let bit_codes: Vec<BitCode> = (0..200u32).map(|i| {
+            let bools: Vec<bool> = (0..16).map(|j| (i.wrapping_mul(2_654_435_761) >> (j / 2)) & 1 == 1).collect();
+            BitCode::from_bools(&bools)
+        }).collect();
+        let partition = Partition::decorrelated(bit_codes.iter().map(|c| c.view()), 16, 8);
+        assert_eq!(partition.len(), 2);
+        for substring in &partition.substrings {
+            assert_eq!(substring.positions.len(), 8);
+            for k in 0..8 {
+                assert!(!(substring.positions.contains(&(2 * k)) && substring.positions.contains(&(2 * k + 1))));
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors() {
+        let mut keys: Vec<u64> = Vec::new();
+        for_each_neighbor(0b0101, 4, 0, 2, &mut |key| keys.push(key));
+        keys.sort();
+        assert_eq!(keys, vec![0b0000, 0b0011, 0b0110, 0b1001, 0b1100, 0b1111]);
+        assert_eq!(binomial(4, 2), keys.len());
+        assert_eq!((binomial(64, 0), binomial(64, 1), binomial(64, 32), binomial(3, 4)), (1, 64, 1_832_624_140_942_590_534, 0));
+        let bit_codes: Vec<BitCode> = ["110010", "110011"].iter().map(|s| BitCode::from_bit_string(s)).collect();
+        let mut index = BitCodeIndex::new();
+        index.build(Partition::contiguous(6, 4), bit_codes.len(), |i| bit_codes[i].view());
+        // Substrings of 4 and 2 bits.
+        assert_eq!(index.num_keys_at_distance(1), 6);
+        assert_eq!(index.num_keys_at_distance(3), 4);
     }
 }
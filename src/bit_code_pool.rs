@@ -1,14 +1,25 @@
-use bit_code::BitCode;
-use bit_code_index::BitCodeIndex;
+use bit_code::{BitCode, BitCodeRef};
+use bit_code_index::{BitCodeIndex, Partition};
+pub use bit_code_index::{IndexStats, TableStats};
+use dendrogram::{spanning_links, Dendrogram};
 use encoding::string_to_bit_code;
 use encoding_options::EncodingOptions;
-use std::collections::HashSet;
+use error::BitCodeError;
+use hamming;
+use rayon::prelude::*;
+use resolution::{ConstrainedSets, Constraints, Resolution};
+use significance::{radius_for_significance, EmpiricalNull};
+use similarity::{estimated_cosine, CosineEstimate};
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
 use utils::{get_num_indexes, num_blocks_needed, FastHasher};
 
 
 #[derive(Debug)]
 pub struct BitCodePool {
-    bit_codes: Vec<BitCode>,                // Bit codes in the pool.
+    blocks: Vec<u64>,                       // Bit codes in the pool, packed contiguously as num_blocks u64 blocks each.
     ids: Vec<u64>,                          // Identifiers associated with bit codes (e.g. primary keys in database representation).
     index: BitCodeIndex,                    // Multi-index to enable sublinear-time searching.
     num_blocks: usize,                      // Number of u64 blocks in bit codes.
@@ -19,37 +30,72 @@ pub struct BitCodePool {
 impl BitCodePool {
     pub fn new(encoding_options: EncodingOptions) -> Self {
         BitCodePool {
-            bit_codes: Vec::new(),
+            blocks: Vec::new(),
             ids: Vec::new(),
             index: BitCodeIndex::new(),
             num_blocks: num_blocks_needed(encoding_options.num_bits()),
-            encoding_options: encoding_options,
+            encoding_options,
         }
     }
 
     // Add a bit code created from a string to the pool.
     pub fn add(&mut self, string: &str, id: u64) {
-        let bit_code = string_to_bit_code(&string, &self.encoding_options);
-        self.bit_codes.push(bit_code);
+        let bit_code = string_to_bit_code(string, &self.encoding_options);
+        self.blocks.extend_from_slice(bit_code.blocks());
+        self.ids.push(id);
+    }
+
+    /// Adds a bit code to the pool, e.g. one returned by bit_code. Panics if it is not the pool's length.
+    pub fn add_bit_code(&mut self, bit_code: &BitCode, id: u64) {
+        assert_eq!(bit_code.len(), self.num_bits(), "bit code length does not match the pool");
+        self.blocks.extend_from_slice(bit_code.blocks());
         self.ids.push(id);
     }
 
     // Return a bit code for a string, derived in the same way as bit codes in the pool.
     pub fn bit_code(&self, string: &str) -> BitCode {
-        string_to_bit_code(&string, &self.encoding_options)
+        string_to_bit_code(string, &self.encoding_options)
     }
 
-    // Get a BitCode from the pool.
-    pub fn get(&self, i: usize) -> Option<&BitCode> {
+    // Get a view of a bit code in the pool.
+    pub fn get(&self, i: usize) -> Option<BitCodeRef<'_>> {
         if i < self.len() {
-            return Some(&self.bit_codes[i]);
+            return Some(self.code(i));
         }
-        return None;
+        None
+    }
+
+    // Get the identifier associated with a bit code in the pool.
+    pub fn id(&self, i: usize) -> Option<u64> {
+        self.ids.get(i).cloned()
+    }
+
+    // Iterate over views of the bit codes in the pool, in order.
+    pub fn iter(&self) -> impl Iterator<Item=BitCodeRef<'_>> {
+        let num_bits = self.num_bits();
+        self.blocks.chunks_exact(self.num_blocks).map(move |blocks| BitCodeRef::new(blocks, num_bits))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.bit_codes.len()
+        self.ids.len()
+    }
+
+    // View of the i-th bit code, without bounds checking against len().
+    #[inline]
+    fn code(&self, i: usize) -> BitCodeRef<'_> {
+        BitCodeRef::new(&self.blocks[(i * self.num_blocks)..((i + 1) * self.num_blocks)], self.num_bits())
+    }
+
+    // Number of codes covered by the multi-index; codes added since it was built are not.
+    #[inline]
+    pub(crate) fn num_indexed(&self) -> usize {
+        self.index.num_values()
     }
 
     #[inline]
@@ -58,93 +104,631 @@ impl BitCodePool {
     }
 
     // TODO: Figure out a way to expire the index when new bit codes added?
-    // Set multi-index on the bit codes currently in the pool.
-    pub fn index(&mut self, mut bits_per_index: usize) {
+    // Set multi-index on the bit codes currently in the pool, using balanced substrings of at most bits_per_index bits (and at most 64).
+    pub fn index(&mut self, bits_per_index: usize) {
+        self.index_with_partition(bits_per_index, PartitionStrategy::Balanced);
+    }
+
+    /// Sets the multi-index on the bit codes currently in the pool, with substrings of at most bits_per_index bits (and at most 64) chosen according to strategy.
+    pub fn index_with_partition(&mut self, mut bits_per_index: usize, strategy: PartitionStrategy) {
+        if bits_per_index > 64 { bits_per_index = 64; }
+        if bits_per_index > self.num_bits() { bits_per_index = self.num_bits() }
         if bits_per_index == 0 { bits_per_index = 1; }
-        if bits_per_index > self.encoding_options.num_bits() { bits_per_index = self.encoding_options.num_bits() }
-        // Number of indexes.
-        let num_indexes = get_num_indexes(self.encoding_options.num_bits(), bits_per_index);
+        let num_bits = self.num_bits();
+        let partition = match strategy {
+            PartitionStrategy::Contiguous => Partition::contiguous(num_bits, bits_per_index),
+            PartitionStrategy::Balanced => Partition::balanced(num_bits, bits_per_index),
+            PartitionStrategy::Random { seed } => Partition::random(num_bits, bits_per_index, seed),
+            PartitionStrategy::Decorrelated => {
+                // Estimate correlations from an evenly spaced sample of the pool.
+                let step = self.len() / PARTITION_SAMPLE_SIZE + 1;
+                Partition::decorrelated(self.iter().step_by(step), num_bits, bits_per_index)
+            }
+        };
         // Construct index.
-        self.index.init(bits_per_index, num_indexes);
-        for (i, bit_code) in self.bit_codes.iter().enumerate() {
-            let index_values = bit_code.index_values(bits_per_index);
-            self.index.add(&index_values, i);
-        }
+        let start = Instant::now();
+        let mut index = BitCodeIndex::new();
+        index.build(partition, self.len(), |i| self.code(i));
+        self.index = index;
+        debug_log!("index built over {} codes with {} tables of up to {} bits in {:?}", self.len(), self.index.len(), self.index.max_substring_length(), start.elapsed());
+    }
+
+    /// Sets the multi-index with a substring length chosen from the pool size, around log2 of the number of codes as the multi-index hashing paper recommends, and returns the chosen parameters.
+    pub fn index_auto(&mut self) -> IndexParameters {
+        let bits_per_index = auto_bits_per_index(self.len(), self.num_bits(), None);
+        self.index(bits_per_index);
+        IndexParameters::new(self.num_bits(), bits_per_index)
+    }
+
+    /// As index_auto, but shortens the substrings if necessary so that searches up to radius can use the index.
+    pub fn index_auto_for_radius(&mut self, radius: usize) -> IndexParameters {
+        let bits_per_index = auto_bits_per_index(self.len(), self.num_bits(), Some(radius));
+        self.index(bits_per_index);
+        IndexParameters::new(self.num_bits(), bits_per_index)
     }
 
+    /// Returns statistics on the multi-index's buckets.
+    pub fn index_stats(&self) -> IndexStats {
+        self.index.stats()
+    }
+
+    // Print a summary of the multi-index's buckets.
     pub fn index_show(&self) {
-        println!("{:?}", self.index);
+        print!("{}", self.index.stats());
     }
 
     pub fn num_bits(&self) -> usize {
         self.encoding_options.num_bits()
     }
 
+    /// Groups the bit codes into entity sets by star clustering at the given radius. See resolve_entities_with_method.
     pub fn resolve_entities(&self, radius: usize) -> Vec<Vec<usize>> {
-        // Initialize indices of bit codes to search through.
-        let mut population: HashSet<usize, FastHasher> = HashSet::with_capacity_and_hasher(self.len(), FastHasher::default());
-        for i in 0..self.len() { population.insert(i); }
-        // Compute entity sets.
-        let mut entity_sets: Vec<Vec<usize>> = Vec::new();
-        while !population.is_empty() {
-            let mut entity_set: Vec<usize> = Vec::new();
-            let i = population.iter().next().unwrap().clone();
-            for j in &population {
-                if self.bit_codes[i].hamming_distance(&self.bit_codes[*j]) <= radius { entity_set.push(*j); }
-            }
-            for i in &entity_set { population.remove(i); }
-            entity_sets.push(entity_set);
+        self.resolve_entities_with_method(radius, ClusteringMethod::Star)
+    }
+
+    /// Resolves the bit codes into entities clustered according to method, identified by the ids of their records. Unlike resolve_entities, each entity has a stable id: the smallest id among its records. Codes sharing an id are taken to be versions of one record, so are always in the same entity.
+    pub fn resolve(&self, radius: usize, method: ClusteringMethod) -> Resolution {
+        self.resolve_with_constraints(radius, method, &Constraints::new()).expect("resolution without constraints cannot conflict")
+    }
+
+    /// As resolve, honouring must-link and cannot-link constraints between record ids. See resolve_entities_with_constraints.
+    pub fn resolve_with_constraints(&self, radius: usize, method: ClusteringMethod, constraints: &Constraints) -> Result<Resolution, BitCodeError> {
+        let entity_sets = self.resolve_entities_with_constraints(radius, method, constraints)?;
+        Ok(Resolution::from_entity_sets(entity_sets.into_iter().map(|entity_set| entity_set.into_iter().map(|i| self.ids[i]).collect())))
+    }
+
+    /// Groups the bit codes into entity sets of indices, clustered according to method. Each set is in ascending order and sets are ordered by their smallest index, so the result does not vary from run to run. Codes sharing an id are always in the same set.
+    pub fn resolve_entities_with_method(&self, radius: usize, method: ClusteringMethod) -> Vec<Vec<usize>> {
+        self.resolve_entities_with_constraints(radius, method, &Constraints::new()).expect("resolution without constraints cannot conflict")
+    }
+
+    /// As resolve_entities_with_method, honouring constraints between record ids. Records that must link are always in the same set, and merges that would join records that cannot link are refused. Returns an error if the must-links join a cannot-link pair.
+    pub fn resolve_entities_with_constraints(&self, radius: usize, method: ClusteringMethod, constraints: &Constraints) -> Result<Vec<Vec<usize>>, BitCodeError> {
+        let mut sets = self.constrained_sets(constraints)?;
+        match method {
+            // Each group of must-linked codes, including codes sharing an id, is a unit seeded by its first code.
+            ClusteringMethod::Star => {
+                let mut assigned: Vec<bool> = vec![false; self.len()];
+                let mut entity_sets: Vec<Vec<usize>> = Vec::new();
+                let block_size = STAR_SEEDS_PER_THREAD * rayon::current_num_threads();
+                let mut next = 0;
+                while next < self.len() {
+                    // Look up the neighbours of the next block of unassigned codes in parallel. An earlier seed in the block may still take a code, leaving its neighbours unused.
+                    let mut seeds: Vec<usize> = Vec::with_capacity(block_size);
+                    while next < self.len() && seeds.len() < block_size {
+                        if !assigned[next] { seeds.push(next); }
+                        next += 1;
+                    }
+                    let neighbor_lists: Vec<Vec<(usize, usize)>> = seeds.par_iter().map(|&i| self.later_neighbors(i, radius)).collect();
+                    for (i, neighbors) in seeds.into_iter().zip(neighbor_lists) {
+                        if assigned[i] { continue; }
+                        // Codes before the seed are all assigned already, and so are whole units.
+                        for &k in sets.members(i) { assigned[k] = true; }
+                        for (j, _) in neighbors {
+                            if assigned[j] { continue; }
+                            for &k in sets.members(j) { assigned[k] = true; }
+                            if !sets.union(i, j) {
+                                for &k in sets.members(j) { assigned[k] = false; }
+                            }
+                        }
+                        let mut entity_set = sets.members(i).to_vec();
+                        entity_set.sort_unstable();
+                        entity_sets.push(entity_set);
+                    }
+                }
+                Ok(entity_sets)
+            },
+            // With cannot-links, links are considered in ascending order of distance, so closer links take precedence over ones they conflict with. Without them, a spanning forest of the links joins the same codes.
+            ClusteringMethod::SingleLinkage => {
+                let links = if sets.has_cannot_links() { self.later_links(radius) } else { self.spanning_later_links(radius) };
+                for (_, i, j) in links { sets.union(i, j); }
+                Ok(sets.groups())
+            },
         }
-        entity_sets
     }
 
-    /// Returns the indices of bit codes with Hamming distance <= radius from the needle.
-    pub fn search(&self, needle: &BitCode, radius: usize) -> Vec<usize> {
-        let mut indices: Vec<usize> = Vec::new();
-        for i in 0..self.bit_codes.len() {
-            if self.bit_codes[i].hamming_distance(&needle) <= radius { indices.push(i); }
-        }
-        indices
-    }
-
-    /// Returns the k nearest neighbors of the needle.
-    pub fn search_knn(&self, needle: &BitCode, k: usize) -> Vec<SearchResult> {
-        let mut results: Vec<SearchResult> = Vec::with_capacity(k);
-        for idx in 0..self.bit_codes.len() {
-            let d = self.bit_codes[idx].hamming_distance(&needle);
-            let n = results.len();
-            if (n == 0) || (d <= results[n - 1].distance) {
-                results.push(SearchResult{ idx: idx, distance: d });
-                // Sort result by ascending distance, and truncate to length k without losing any entries that are the same distance from the needle as the kth (these would be arbitrary exclusions).
-                results.sort_by_key(|key| key.by_distance());
-                if results.len() > k {
-                    let distance_threshold = results[k - 1].distance;
-                    results.retain(|sr| sr.distance <= distance_threshold);
+    // Returns sets of positions joined by the must-links and by shared ids, refusing unions across cannot-links. Constraints on ids not in the pool are ignored.
+    fn constrained_sets(&self, constraints: &Constraints) -> Result<ConstrainedSets, BitCodeError> {
+        let mut positions: HashMap<u64, Vec<usize>, FastHasher> = HashMap::default();
+        if !constraints.is_empty() {
+            for (i, &id) in self.ids.iter().enumerate() { positions.entry(id).or_default().push(i); }
+        }
+        let position_pairs = |pairs: &[(u64, u64)]| -> Vec<(usize, usize)> {
+            let mut position_pairs: Vec<(usize, usize)> = Vec::new();
+            for &(a, b) in pairs {
+                if let (Some(positions_a), Some(positions_b)) = (positions.get(&a), positions.get(&b)) {
+                    for &i in positions_a {
+                        for &j in positions_b { position_pairs.push((i, j)); }
+                    }
                 }
             }
+            position_pairs
+        };
+        let mut must = position_pairs(constraints.must_links());
+        must.extend(self.repeated_id_pairs());
+        ConstrainedSets::new(self.len(), &must, &position_pairs(constraints.cannot_links()))
+            .map_err(|(i, j)| BitCodeError::ConstraintConflict { a: self.ids[i], b: self.ids[j] })
+    }
+
+    /// Builds the single-linkage dendrogram of the bit codes up to max_radius in one pass over their neighbours, from which entity sets at any radius up to it can be cut without searching again. Codes sharing an id merge at distance 0.
+    pub fn dendrogram(&self, max_radius: usize) -> Dendrogram {
+        let mut links = self.spanning_later_links(max_radius);
+        links.extend(self.repeated_id_pairs().into_iter().map(|(i, j)| (0, i, j)));
+        Dendrogram::from_links(self.ids.clone(), max_radius, links)
+    }
+
+    // Returns pairs of positions holding the same id, each paired with the previous position holding it, which together join every group of positions sharing an id.
+    fn repeated_id_pairs(&self) -> Vec<(usize, usize)> {
+        let mut last_positions: HashMap<u64, usize, FastHasher> = HashMap::with_capacity_and_hasher(self.len(), FastHasher::default());
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        for (i, &id) in self.ids.iter().enumerate() {
+            if let Some(previous) = last_positions.insert(id, i) { pairs.push((previous, i)); }
+        }
+        pairs
+    }
+
+    // Returns every link (distance, i, j) between codes i < j within radius of one another, in ascending order. The neighbours are found in parallel.
+    fn later_links(&self, radius: usize) -> Vec<(usize, usize, usize)> {
+        let mut links: Vec<(usize, usize, usize)> = (0..self.len()).into_par_iter()
+            .flat_map_iter(|i| self.later_neighbors(i, radius).into_iter().map(move |(j, d)| (d, i, j)))
+            .collect();
+        links.sort_unstable();
+        links
+    }
+
+    // Returns a minimum spanning forest of the links between codes within radius of one another, which joins the same codes as all of them at every distance up to radius. Each parallel task reduces its links to a spanning forest whenever they outgrow the pool, and forests are reduced again as they are combined, so memory is bounded by the pool size per task and one code's neighbours.
+    fn spanning_later_links(&self, radius: usize) -> Vec<(usize, usize, usize)> {
+        let n = self.len();
+        (0..n).into_par_iter()
+            .fold(Vec::new, |mut links: Vec<(usize, usize, usize)>, i| {
+                links.extend(self.later_neighbors(i, radius).into_iter().map(|(j, d)| (d, i, j)));
+                if links.len() >= 2 * n { links = spanning_links(n, links); }
+                links
+            })
+            .map(|links| spanning_links(n, links))
+            .reduce(Vec::new, |mut links, other| {
+                links.extend(other);
+                spanning_links(n, links)
+            })
+    }
+
+    // Returns the codes after i that are within radius of it and their distances, in ascending order.
+    fn later_neighbors(&self, i: usize, radius: usize) -> Vec<(usize, usize)> {
+        self.neighbors_from(self.code(i), i + 1, radius)
+    }
+
+    // Whether the multi-index can answer a search with the radius and is expected to return few enough candidates to beat a batch scan.
+    fn index_pays_off(&self, radius: usize) -> bool {
+        !self.index.is_empty() && radius <= self.index.max_searchable_radius()
+            && self.index.expected_candidates() < INDEX_MAX_CANDIDATE_FRACTION * self.index.num_values() as f64
+    }
+
+    // Returns the codes at positions >= first that are within radius of the needle and their distances, in ascending order. The multi-index is used when it can answer the radius more cheaply than a scan, and codes it does not cover are scanned.
+    pub(crate) fn neighbors_from(&self, needle: BitCodeRef, first: usize, radius: usize) -> Vec<(usize, usize)> {
+        let num_indexed = self.index.num_values();
+        let mut neighbors: Vec<(usize, usize)> = Vec::new();
+        let mut scan_from = first;
+        if self.index_pays_off(radius) && scan_from < num_indexed {
+            // A code matching the needle in several substrings is found once per substring, so candidates are gathered in the output and deduplicated before their distances are computed.
+            self.index.for_each_candidate(&self.index.keys(needle), |c| if c >= first { neighbors.push((c, 0)); });
+            neighbors.sort_unstable();
+            neighbors.dedup();
+            neighbors.retain_mut(|neighbor| match self.code(neighbor.0).within(needle, radius) {
+                Some(d) => { neighbor.1 = d; true },
+                None => false,
+            });
+            // Codes added since the index was built are not in it.
+            scan_from = num_indexed;
+        }
+        if scan_from < self.len() {
+            let later_blocks = &self.blocks[(scan_from * self.num_blocks)..];
+            for (offset, d) in hamming::scan(needle.blocks(), later_blocks).enumerate() {
+                if d <= radius { neighbors.push((scan_from + offset, d)); }
+            }
+        }
+        neighbors
+    }
+
+    /// Returns every pair of a bit code in this pool and one in the other pool within radius of one another, as (id, other_id, distance), ordered by the positions of the codes in this pool and then in the other. Each code of the smaller pool is looked up in the larger, using its multi-index when it can answer the radius, in parallel. Returns an error if the pools were encoded differently.
+    pub fn join(&self, other: &BitCodePool, radius: usize) -> Result<Vec<(u64, u64, usize)>, BitCodeError> {
+        self.check_compatible(other)?;
+        let mut pairs: Vec<(usize, usize, usize)> = Vec::new();
+        if self.len() <= other.len() {
+            for (i, neighbors) in (0..self.len()).into_par_iter().map(|i| other.neighbors_from(self.code(i), 0, radius)).collect::<Vec<_>>().into_iter().enumerate() {
+                pairs.extend(neighbors.into_iter().map(|(j, d)| (i, j, d)));
+            }
+        } else {
+            for (j, neighbors) in (0..other.len()).into_par_iter().map(|j| self.neighbors_from(other.code(j), 0, radius)).collect::<Vec<_>>().into_iter().enumerate() {
+                pairs.extend(neighbors.into_iter().map(|(i, d)| (i, j, d)));
+            }
+            pairs.sort_unstable();
+        }
+        Ok(pairs.into_iter().map(|(i, j, d)| (self.ids[i], other.ids[j], d)).collect())
+    }
+
+    /// Returns, for each bit code in this pool, its k nearest neighbors in the other pool including any ties with the kth, as (id, other_id, distance). Pairs are ordered by the position of the code in this pool and then by ascending distance. Neighbors are found using the other pool's multi-index, as by search_knn_with_index, in parallel. Returns an error if the pools were encoded differently.
+    pub fn join_knn(&self, other: &BitCodePool, k: usize) -> Result<Vec<(u64, u64, usize)>, BitCodeError> {
+        self.check_compatible(other)?;
+        let mut pairs: Vec<(u64, u64, usize)> = Vec::new();
+        for (i, results) in (0..self.len()).into_par_iter().map(|i| other.search_knn_with_index(self.code(i), k)).collect::<Vec<_>>().into_iter().enumerate() {
+            pairs.extend(results.into_iter().map(|r| (self.ids[i], other.ids[r.idx()], r.distance())));
+        }
+        Ok(pairs)
+    }
+
+    // Check that codes in the other pool are comparable with codes in this one.
+    fn check_compatible(&self, other: &BitCodePool) -> Result<(), BitCodeError> {
+        if !self.encoding_options.is_compatible(&other.encoding_options) { return Err(BitCodeError::IncompatibleEncoding); }
+        Ok(())
+    }
+
+    /// Returns an iterator over every pair of bit codes in the pool within radius of one another, as (id_a, id_b, distance) with id_a the id of the earlier code. Each pair is returned once, ordered by the position of the earlier and then the later code in the pool. Pairs are found a code at a time, using the multi-index when it can answer the radius, so they need not all be held in memory.
+    pub fn self_join(&self, radius: usize) -> SelfJoin<'_> {
+        SelfJoin { pool: self, radius, next_code: 0, neighbors: Vec::new(), position: 0 }
+    }
+
+    /// Calls f with every pair of bit codes in the pool within radius of one another, as in self_join. Neighbours are found a block of codes at a time in parallel, but f is called from this thread in the same order as self_join returns the pairs.
+    pub fn self_join_for_each<F: FnMut(u64, u64, usize)>(&self, radius: usize, mut f: F) {
+        for block_start in (0..self.len()).step_by(RESOLVE_BLOCK_SIZE) {
+            let block_end = min(block_start + RESOLVE_BLOCK_SIZE, self.len());
+            for (i, neighbors) in (block_start..block_end).zip((block_start..block_end).into_par_iter().map(|i| self.later_neighbors(i, radius)).collect::<Vec<_>>()) {
+                for (j, d) in neighbors { f(self.ids[i], self.ids[j], d); }
+            }
+        }
+    }
+
+    /// Returns the Hamming distance from the needle to every bit code in the pool, in order.
+    pub fn distances<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N) -> Vec<usize> {
+        self.scan(needle.into()).collect()
+    }
+
+    /// Returns the indices of bit codes with Hamming distance <= radius from the needle. The needle can be a &BitCode or a view of a code in a pool.
+    pub fn search<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, radius: usize) -> Vec<usize> {
+        self.search_with_stats(needle, radius).0
+    }
+
+    /// As search, also returning statistics on the work done.
+    pub fn search_with_stats<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, radius: usize) -> (Vec<usize>, SearchStats) {
+        let start = Instant::now();
+        let mut indices: Vec<usize> = Vec::new();
+        for (i, d) in self.scan(needle.into()).enumerate() {
+            if d <= radius { indices.push(i); }
+        }
+        let stats = SearchStats::linear(self.len(), indices.len(), start.elapsed());
+        debug_log!("search radius={} {}", radius, stats);
+        (indices, stats)
+    }
+
+    /// Returns the indices of bit codes whose distance from the needle would have a p-value of at most the significance level if the codes were unrelated, that is those within significance::radius_for_significance of it.
+    pub fn search_significant<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, significance: f64) -> Vec<usize> {
+        match radius_for_significance(self.num_bits(), significance) {
+            Some(radius) => self.search(needle, radius),
+            None => Vec::new(),
+        }
+    }
+
+    /// As search_significant, with p-values from an empirical null sampled from this pool, or one encoded the same way, in place of the binomial null.
+    pub fn search_significant_with_null<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, significance: f64, null: &EmpiricalNull) -> Vec<usize> {
+        match null.radius_for_significance(significance) {
+            Some(radius) => self.search(needle, radius),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the k nearest neighbors of the needle, including any ties with the kth, ordered by ascending distance.
+    pub fn search_knn<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, k: usize) -> Vec<SearchResult> {
+        self.search_knn_with_mode(needle, k, KnnMode::IncludeTies)
+    }
+
+    /// As search_knn, also returning statistics on the work done.
+    pub fn search_knn_with_stats<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, k: usize) -> (Vec<SearchResult>, SearchStats) {
+        let start = Instant::now();
+        let results = self.search_knn(needle, k);
+        let stats = SearchStats::linear(self.len(), results.len(), start.elapsed());
+        debug_log!("search_knn k={} {}", k, stats);
+        (results, stats)
+    }
+
+    /// Returns the k nearest neighbors of the needle, ordered by ascending distance, with ties at the kth distance treated according to mode.
+    pub fn search_knn_with_mode<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, k: usize, mode: KnnMode) -> Vec<SearchResult> {
+        nearest(self.scan(needle.into()).enumerate(), k, mode, self.num_bits())
+    }
+
+    /// Returns the k nearest neighbors of the needle using the multi-index, including any ties with the kth, ordered by ascending distance. The results are the same as those of search_knn.
+    pub fn search_knn_with_index<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, k: usize) -> Vec<SearchResult> {
+        self.search_knn_with_index_and_mode(needle, k, KnnMode::IncludeTies)
+    }
+
+    /// Returns the k nearest neighbors of the needle using the multi-index, with ties at the kth distance treated according to mode. The results are the same as those of search_knn_with_mode.
+    // Substrings are probed at increasing substring distances, as in the multi-index hashing paper. Small or unindexed pools use a linear scan.
+    pub fn search_knn_with_index_and_mode<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, k: usize, mode: KnnMode) -> Vec<SearchResult> {
+        let needle = needle.into();
+        let num_indexed = self.index.num_values();
+        if self.index.is_empty() || num_indexed < KNN_INDEX_MIN_POOL_SIZE || k >= num_indexed {
+            return self.search_knn_with_mode(needle, k, mode);
         }
-        results
+        let num_indexes = self.index.len();
+        let needle_index_values = self.index.keys(needle);
+        let mut seen: HashSet<usize, FastHasher> = HashSet::default();
+        let mut found: Vec<(usize, usize)> = Vec::new();
+        // Codes added since the index was built are not in it, so are always compared.
+        for i in num_indexed..self.len() { found.push((i, self.code(i).hamming_distance(needle))); }
+        // Keys probed so far. The number at each substring distance grows combinatorially with the substring length, so once probing would cost more than comparing every code, scan instead.
+        let mut num_probed: usize = 0;
+        for substring_distance in 0..(self.index.max_substring_length() + 1) {
+            num_probed = num_probed.saturating_add(self.index.num_keys_at_distance(substring_distance));
+            if num_probed > self.len() { return self.search_knn_with_mode(needle, k, mode); }
+            self.index.for_each_candidate_at_distance(&needle_index_values, substring_distance, |c| {
+                if seen.insert(c) { found.push((c, self.code(c).hamming_distance(needle))); }
+            });
+            // With m substrings, a code within m * (r + 1) - 1 bits of the needle matches it to within r bits in some substring.
+            let exact_radius = num_indexes * (substring_distance + 1) - 1;
+            if found.iter().filter(|&&(_, d)| d <= exact_radius).count() >= k { break; }
+            // Searches that would visit much of the pool are cheaper as a scan.
+            if 2 * seen.len() > num_indexed { return self.search_knn_with_mode(needle, k, mode); }
+        }
+        debug_log!("search_knn_with_index k={} candidates={} distances={}", k, seen.len(), found.len());
+        found.sort();
+        nearest(found.into_iter(), k, mode, self.num_bits())
+    }
+
+    // Iterate over the distances from the needle to each bit code in the pool, computed by the batch kernel. A needle whose length differs from the pool's is compared as in BitCode::hamming_distance.
+    fn scan(&self, needle: BitCodeRef) -> impl Iterator<Item=usize> + '_ {
+        self.scan_from(needle, 0)
+    }
+
+    // As scan, over the bit codes at positions >= first.
+    fn scan_from(&self, needle: BitCodeRef, first: usize) -> impl Iterator<Item=usize> + '_ {
+        let needle_blocks = needle.blocks();
+        let mut surplus = 0;
+        let mut padded: Vec<u64> = needle_blocks.to_vec();
+        if needle_blocks.len() != self.num_blocks {
+            // Pad or truncate the needle to the pool's block count, counting any truncated set bits separately.
+            padded.resize(self.num_blocks, 0);
+            surplus = needle_blocks[min(self.num_blocks, needle_blocks.len())..].iter().map(|block| block.count_ones() as usize).sum();
+        }
+        hamming::scan(&padded, &self.blocks[(first * self.num_blocks)..]).map(move |d| d + surplus)
+    }
+
+    /// Returns the indices of bit codes with Hamming distance <= radius from the needle using indexed search, in ascending order. Codes added since the index was built are scanned. Returns None if the pool has not been indexed or the radius is beyond index_max_searchable_radius.
+    pub fn search_with_index<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, radius: usize) -> Option<Vec<usize>> {
+        self.search_with_index_and_stats(needle, radius).map(|(indices, _)| indices)
     }
 
-    /// Returns the indices of bit codes with Hamming distance <= radius from the needle using indexed search.
-    pub fn search_with_index(&self, needle: &BitCode, radius: usize) -> Option<Vec<usize>> {
+    /// As search_with_index, also returning statistics on the work done.
+    pub fn search_with_index_and_stats<'a, N: Into<BitCodeRef<'a>>>(&self, needle: N, radius: usize) -> Option<(Vec<usize>, SearchStats)> {
+        let needle = needle.into();
         // Check index is valid for search.
-        if (radius as usize) > self.index.max_searchable_radius() { return None; };
-        // Perform index search.
-        let needle_index_values = needle.index_values(self.index.index_length());
-        let candidate_indices = &self.index.candidate_indices(&needle_index_values);
+        if self.index.is_empty() || radius > self.index.max_searchable_radius() { return None; };
+        let num_indexed = self.index.num_values();
+        // Look up the candidates. A code matching several substrings is found once per substring, so candidates are deduplicated before their distances are computed.
+        let start = Instant::now();
         let mut indices: Vec<usize> = Vec::new();
-        for c in candidate_indices {
-            if self.bit_codes[*c].hamming_distance(&needle) <= radius { indices.push(*c); }
+        self.index.for_each_candidate(&self.index.keys(needle), |c| indices.push(c));
+        indices.sort_unstable();
+        indices.dedup();
+        let candidate_time = start.elapsed();
+        // Codes added since the index was built are not in it, so every one is a candidate.
+        let num_candidates = indices.len() + (self.len() - num_indexed);
+        let start = Instant::now();
+        let mut num_distances = 0;
+        indices.retain(|&c| {
+            num_distances += 1;
+            self.code(c).within(needle, radius).is_some()
+        });
+        for (offset, d) in self.scan_from(needle, num_indexed).enumerate() {
+            num_distances += 1;
+            if d <= radius { indices.push(num_indexed + offset); }
         }
-        Some(indices)
+        let stats = SearchStats {
+            num_candidates,
+            num_distances,
+            num_results: indices.len(),
+            candidate_time,
+            distance_time: start.elapsed(),
+        };
+        debug_log!("search_with_index radius={} {}", radius, stats);
+        Some((indices, stats))
+    }
+}
+
+
+/// Statistics on the work done by a search.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchStats {
+    num_candidates: usize,
+    num_distances: usize,
+    num_results: usize,
+    candidate_time: Duration,
+    distance_time: Duration,
+}
+
+
+impl SearchStats {
+    // Statistics for a linear scan, in which every code is a candidate.
+    fn linear(num_codes: usize, num_results: usize, distance_time: Duration) -> Self {
+        SearchStats { num_candidates: num_codes, num_distances: num_codes, num_results, candidate_time: Duration::from_secs(0), distance_time }
     }
+
+    /// Number of codes considered: every code for a linear scan, or for an indexed search the distinct codes sharing a substring with the needle.
+    pub fn num_candidates(&self) -> usize { self.num_candidates }
+
+    /// Number of Hamming distances computed (including those abandoned early once the radius was exceeded).
+    pub fn num_distances(&self) -> usize { self.num_distances }
+
+    /// Number of results returned.
+    pub fn num_results(&self) -> usize { self.num_results }
+
+    /// Time spent computing the needle's substring keys, looking them up in the index's tables and deduplicating the candidates; zero for a linear scan.
+    pub fn candidate_time(&self) -> Duration { self.candidate_time }
+
+    /// Time spent computing the candidates' distances and selecting results.
+    pub fn distance_time(&self) -> Duration { self.distance_time }
+}
+
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "candidates={} distances={} results={} candidate_time={:?} distance_time={:?}",
+            self.num_candidates, self.num_distances, self.num_results, self.candidate_time, self.distance_time)
+    }
+}
+
+
+/// Iterator over the pairs of bit codes in a pool within a radius of one another. See BitCodePool::self_join.
+pub struct SelfJoin<'a> {
+    pool: &'a BitCodePool,
+    radius: usize,
+    next_code: usize,                       // Position of the next code whose later neighbours are to be found.
+    neighbors: Vec<(usize, usize)>,         // Later neighbours of the previous code, and their distances.
+    position: usize,                        // Position of the next neighbour to return.
 }
 
 
+impl<'a> Iterator for SelfJoin<'a> {
+    type Item = (u64, u64, usize);
+
+    fn next(&mut self) -> Option<(u64, u64, usize)> {
+        while self.position == self.neighbors.len() {
+            if self.next_code >= self.pool.len() { return None; }
+            self.neighbors = self.pool.later_neighbors(self.next_code, self.radius);
+            self.next_code += 1;
+            self.position = 0;
+        }
+        let (j, d) = self.neighbors[self.position];
+        self.position += 1;
+        Some((self.pool.ids[self.next_code - 1], self.pool.ids[j], d))
+    }
+}
+
+
+/// Parameters of a multi-index over a pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexParameters {
+    bits_per_index: usize,
+    num_indexes: usize,
+}
+
+
+impl IndexParameters {
+    fn new(num_bits: usize, bits_per_index: usize) -> Self {
+        IndexParameters { bits_per_index, num_indexes: get_num_indexes(num_bits, bits_per_index) }
+    }
+
+    /// Maximum length of the substrings each index is keyed on.
+    pub fn bits_per_index(&self) -> usize { self.bits_per_index }
+
+    /// Number of substrings, and so of indexes.
+    pub fn num_indexes(&self) -> usize { self.num_indexes }
+
+    /// Largest radius that search_with_index can answer.
+    pub fn max_searchable_radius(&self) -> usize { self.num_indexes - 1 }
+}
+
+
+// Returns a substring length of about log2(num_codes) bits, shortened if necessary so there are enough substrings to search up to target_radius.
+fn auto_bits_per_index(num_codes: usize, num_bits: usize, target_radius: Option<usize>) -> usize {
+    let mut bits_per_index = (num_codes as f64).log2().round() as usize;
+    if bits_per_index < 1 { bits_per_index = 1; }
+    if bits_per_index > num_bits { bits_per_index = num_bits; }
+    if let Some(radius) = target_radius {
+        while bits_per_index > 1 && get_num_indexes(num_bits, bits_per_index) < radius + 1 {
+            bits_per_index -= 1;
+        }
+    }
+    bits_per_index
+}
+
+
+// Maximum number of codes sampled to choose a decorrelated partition.
+const PARTITION_SAMPLE_SIZE: usize = 1_000;
+
+
+/// How the bits of codes are split into the substrings keying the multi-index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    /// Consecutive runs of bits_per_index bits. When bits_per_index does not divide the code length the last substring is shorter, and its table has fewer, fuller buckets.
+    Contiguous,
+    /// Consecutive runs of bits whose lengths differ by at most one bit.
+    Balanced,
+    /// Balanced substrings of randomly chosen bits, so that correlated neighbouring bits are unlikely to share a table. The same seed gives the same partition.
+    Random { seed: u64 },
+    /// Balanced substrings chosen so that bits correlated across a sample of the pool are placed in different tables.
+    Decorrelated,
+}
+
+
+// Number of codes whose neighbours are found in parallel at a time by self_join_for_each.
+const RESOLVE_BLOCK_SIZE: usize = 4_096;
+
+
+// Number of upcoming seeds per thread whose neighbours star clustering looks up in parallel at a time. Small blocks waste few lookups on codes an earlier seed in the block takes.
+const STAR_SEEDS_PER_THREAD: usize = 16;
+
+
+// Indexed radius searches expecting more candidates per query than this fraction of the indexed codes scan instead. Candidates are checked one at a time, which costs more per code than the batch scan; at 128 bits the index was still faster at 0.44 and slower at 0.94.
+const INDEX_MAX_CANDIDATE_FRACTION: f64 = 0.5;
+
+
+// Pools with fewer indexed codes than this are searched for nearest neighbors by linear scan.
+const KNN_INDEX_MIN_POOL_SIZE: usize = 1_000;
+
+
+/// How resolve_entities groups bit codes into entity sets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClusteringMethod {
+    /// Take the first unassigned code in pool order as a seed, and group it with every unassigned code within the radius of it. Codes linked only through other codes (A~B~C but not A~C) may be split.
+    Star,
+    /// Group codes connected by any chain of codes each within the radius of the next (connected components).
+    SingleLinkage,
+}
+
+
+/// How search_knn treats codes tied with the kth nearest neighbor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KnnMode {
+    /// Return every code at the same distance as the kth nearest neighbor, so more than k results may be returned. Excluding some of them would be arbitrary.
+    IncludeTies,
+    /// Return exactly k results (or all codes if there are fewer), breaking ties at the kth distance in favour of codes earlier in the pool.
+    Strict,
+}
+
+
+// Returns the k nearest of a sequence of (idx, distance) pairs as SearchResults for num_bits-bit codes, ordered by ascending distance and then idx. Pairs must be in ascending idx order.
+// Distances are bounded integers, so candidates are kept in per-distance buckets (a counting sort). Buckets beyond the kth nearest distance seen so far are discarded as soon as the closer buckets hold k candidates, and later distances beyond it are skipped without being stored.
+pub(crate) fn nearest<I: Iterator<Item=(usize, usize)>>(distances: I, k: usize, mode: KnnMode, num_bits: usize) -> Vec<SearchResult> {
+    if k == 0 { return Vec::new(); }
+    let mut buckets: Vec<Vec<usize>> = Vec::new();
+    let mut count: usize = 0;
+    // Largest distance that can still be returned.
+    let mut threshold = usize::MAX;
+    for (idx, d) in distances {
+        if d > threshold || (mode == KnnMode::Strict && d == threshold && count >= k) { continue; }
+        if d >= buckets.len() { buckets.resize(d + 1, Vec::new()); }
+        buckets[d].push(idx);
+        count += 1;
+        // Drop the furthest bucket while the closer buckets hold at least k candidates.
+        while let Some(n) = buckets.last().map(|bucket| bucket.len()) {
+            if count - n < k { break; }
+            count -= n;
+            buckets.pop();
+        }
+        if count >= k { threshold = buckets.len() - 1; }
+    }
+    let mut results: Vec<SearchResult> = Vec::with_capacity(count);
+    for (distance, bucket) in buckets.iter().enumerate() {
+        for idx in bucket { results.push(SearchResult{ idx: *idx, distance, num_bits }); }
+    }
+    if mode == KnnMode::Strict { results.truncate(k); }
+    results
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct SearchResult {
     idx: usize,
     distance: usize,
+    num_bits: usize,                        // Length of the codes searched.
 }
 
 
@@ -156,15 +740,24 @@ impl SearchResult {
     pub fn distance(&self) -> usize { self.distance }
 
     pub fn idx(&self) -> usize { self.idx }
+
+    /// Estimated cosine similarity of the needle and the result. See similarity::estimated_cosine.
+    pub fn estimated_cosine(&self) -> CosineEstimate { estimated_cosine(self.distance, self.num_bits) }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::BitCodePool;
+    use bit_code::BitCode;
+    use super::{auto_bits_per_index, nearest, BitCodePool, ClusteringMethod, KnnMode, PartitionStrategy};
     use encoding_options::EncodingOptions;
+    use error::BitCodeError;
+    use resolution::Constraints;
+    use significance::EmpiricalNull;
+    use std::time::{Duration, Instant};
     use test::Bencher;
-    use utils::random_string;
+    use union_find::UnionFind;
+    use utils::{random_bit_string, random_string};
 
     #[test]
     fn index_search() {
@@ -181,7 +774,7 @@ mod tests {
         let mut ids2 = bit_code_pool.search_with_index(needle, hamming_radius).unwrap();
         // Confirm results of unindexed and indexed search are the same.
         assert_eq!(ids1.len(), ids2.len());
-        assert!(ids1.len() > 0);
+        assert!(!ids1.is_empty());
         ids1.sort();
         ids2.sort();
         for i in 0..ids1.len() {
@@ -189,6 +782,298 @@ mod tests {
         }
     }
 
+    #[test]
+    fn index_partitions() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 100, 500));
+        for id in 0..2_000 { bit_code_pool.add(&random_string(5), id); }
+        let strategies = [PartitionStrategy::Contiguous, PartitionStrategy::Balanced, PartitionStrategy::Random { seed: 1 }, PartitionStrategy::Decorrelated];
+        for &strategy in &strategies {
+            bit_code_pool.index_with_partition(12, strategy);
+            assert_eq!(bit_code_pool.index_max_searchable_radius(), 8);
+            for i in 0..10 {
+                let needle = bit_code_pool.get(i).unwrap();
+                assert_eq!(bit_code_pool.search_with_index(needle, 8), Some(bit_code_pool.search(needle, 8)));
+                assert_eq!(bit_code_pool.search_knn_with_index(needle, 5), bit_code_pool.search_knn(needle, 5));
+            }
+            // A needle shorter than the pool's codes is compared as if zero-padded.
+            let short_needle = BitCode::from_blocks(vec![bit_code_pool.get(0).unwrap().blocks()[0]], 64);
+            assert_eq!(bit_code_pool.search_with_index(&short_needle, 8), Some(bit_code_pool.search(&short_needle, 8)));
+        }
+    }
+
+    #[test]
+    fn search_stats() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 64, 500));
+        for id in 0..2_000 { bit_code_pool.add(&random_string(5), id); }
+        bit_code_pool.index(8);
+        let needle = bit_code_pool.get(0).unwrap();
+        let (indices, stats) = bit_code_pool.search_with_stats(needle, 7);
+        assert_eq!(indices, bit_code_pool.search(needle, 7));
+        assert_eq!((stats.num_candidates(), stats.num_distances(), stats.num_results()), (2_000, 2_000, indices.len()));
+        let (indexed, stats) = bit_code_pool.search_with_index_and_stats(needle, 7).unwrap();
+        assert_eq!(indexed, indices);
+        assert_eq!(stats.num_results(), indices.len());
+        assert!(stats.num_candidates() >= indices.len() && stats.num_candidates() <= 2_000);
+        assert_eq!(stats.num_distances(), stats.num_candidates());
+        assert!(bit_code_pool.search_with_index_and_stats(needle, 8).is_none());
+        let (results, stats) = bit_code_pool.search_knn_with_stats(needle, 5);
+        assert_eq!(results, bit_code_pool.search_knn(needle, 5));
+        assert_eq!(stats.num_results(), results.len());
+        assert!(format!("{}", stats).starts_with("candidates=2000 distances=2000"));
+        // Codes added after the index was built are scanned, and count as candidates.
+        let needle = needle.to_bit_code();
+        bit_code_pool.add_bit_code(&needle, 2_000);
+        let (indexed, stats) = bit_code_pool.search_with_index_and_stats(&needle, 7).unwrap();
+        assert_eq!(indexed, bit_code_pool.search(&needle, 7));
+        assert_eq!(indexed.last(), Some(&2_000));
+        assert_eq!(stats.num_distances(), stats.num_candidates());
+        // An unindexed pool cannot answer an indexed search.
+        let unindexed_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 64, 500));
+        assert_eq!(unindexed_pool.index_max_searchable_radius(), 0);
+        assert!(unindexed_pool.search_with_index(&needle, 0).is_none());
+    }
+
+    #[test]
+    fn search_significant() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 64, 500));
+        for id in 0..1_000 { bit_code_pool.add(&random_string(5), id); }
+        let needle = bit_code_pool.get(0).unwrap();
+        // At most 8 bits of 64 differ by chance with probability below 1e-9, but 9 bits with probability above it.
+        assert_eq!(bit_code_pool.search_significant(needle, 1e-9), bit_code_pool.search(needle, 8));
+        assert!(bit_code_pool.search_significant(needle, 1e-30).is_empty());
+        assert_eq!(bit_code_pool.search_significant(needle, 1.0).len(), 1_000);
+        // The empirical null cannot resolve p-values below one over its samples plus one.
+        let null = EmpiricalNull::sample(&bit_code_pool, 10_000, 7);
+        let radius = null.radius_for_significance(0.05).unwrap();
+        assert_eq!(bit_code_pool.search_significant_with_null(needle, 0.05, &null), bit_code_pool.search(needle, radius));
+        assert!(bit_code_pool.search_significant_with_null(needle, 1e-9, &null).is_empty());
+        assert_eq!(bit_code_pool.search_significant_with_null(needle, 1.0, &null).len(), 1_000);
+    }
+
+    #[test]
+    fn packed_storage() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 100, 500));
+        let strings = ["alpha", "beta", "gamma"];
+        for (id, string) in strings.iter().enumerate() { bit_code_pool.add(string, id as u64); }
+        // Codes are packed contiguously, 2 blocks per 100-bit code.
+        assert_eq!(bit_code_pool.blocks.len(), 3 * 2);
+        for (i, string) in strings.iter().enumerate() {
+            let bit_code = bit_code_pool.bit_code(string);
+            assert_eq!(bit_code_pool.get(i), Some(bit_code.view()));
+            assert_eq!(bit_code_pool.search(&bit_code, 0), vec![i]);
+        }
+        assert_eq!(bit_code_pool.get(3), None);
+        assert_eq!(bit_code_pool.iter().count(), 3);
+    }
+
+    #[test]
+    fn scans_use_batch_distances() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 200, 500));
+        for id in 0..3_000 { bit_code_pool.add(&random_string(6), id); }
+        let needle = bit_code_pool.bit_code("abcdef");
+        let distances = bit_code_pool.distances(&needle);
+        assert_eq!(distances.len(), bit_code_pool.len());
+        for (i, d) in distances.iter().enumerate() {
+            assert_eq!(*d, bit_code_pool.get(i).unwrap().hamming_distance(needle.view()));
+        }
+        let radius = 10;
+        let expected: Vec<usize> = (0..distances.len()).filter(|&i| distances[i] <= radius).collect();
+        assert_eq!(bit_code_pool.search(&needle, radius), expected);
+        // Needles of other lengths are compared as if padded with unset bits.
+        let mut long_needle = BitCode::new(300);
+        for i in needle.ones() { long_needle.set(i, true); }
+        long_needle.set(250, true);
+        assert_eq!(bit_code_pool.distances(&long_needle)[0], distances[0] + 1);
+        let short_needle = BitCode::new(10);
+        assert_eq!(bit_code_pool.distances(&short_needle)[0], bit_code_pool.get(0).unwrap().count_ones());
+    }
+
+    #[test]
+    fn nearest_distances() {
+        let distances = vec![5, 3, 9, 3, 1, 3, 7, 0, 3];
+        let results = nearest(distances.iter().cloned().enumerate(), 3, KnnMode::IncludeTies, 64);
+        let pairs: Vec<(usize, usize)> = results.iter().map(|r| (r.idx(), r.distance())).collect();
+        assert_eq!(pairs, vec![(7, 0), (4, 1), (1, 3), (3, 3), (5, 3), (8, 3)]);
+        let results = nearest(distances.iter().cloned().enumerate(), 3, KnnMode::Strict, 64);
+        let pairs: Vec<(usize, usize)> = results.iter().map(|r| (r.idx(), r.distance())).collect();
+        assert_eq!(pairs, vec![(7, 0), (4, 1), (1, 3)]);
+        // Larger distances seen before k candidates are collected are kept.
+        let results = nearest(vec![2, 8, 5].into_iter().enumerate(), 2, KnnMode::Strict, 64);
+        let pairs: Vec<(usize, usize)> = results.iter().map(|r| (r.idx(), r.distance())).collect();
+        assert_eq!(pairs, vec![(0, 2), (2, 5)]);
+        assert!(nearest(distances.iter().cloned().enumerate(), 0, KnnMode::IncludeTies, 64).is_empty());
+        assert_eq!(nearest(distances.iter().cloned().enumerate(), 100, KnnMode::Strict, 64).len(), distances.len());
+    }
+
+    #[test]
+    fn search_knn() {
+        // Short strings give many ties.
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 16, 100));
+        for id in 0..2_000 { bit_code_pool.add(&random_string(1), id); }
+        let needle = bit_code_pool.bit_code("q");
+        let mut sorted: Vec<(usize, usize)> = bit_code_pool.distances(&needle).into_iter().enumerate().map(|(i, d)| (d, i)).collect();
+        sorted.sort();
+        for &k in &[1, 5, 50, 500] {
+            let kth = sorted[k - 1].0;
+            let expected: Vec<(usize, usize)> = sorted.iter().cloned().filter(|&(d, _)| d <= kth).collect();
+            let results = bit_code_pool.search_knn(&needle, k);
+            let found: Vec<(usize, usize)> = results.iter().map(|r| (r.distance(), r.idx())).collect();
+            assert_eq!(found, expected);
+            let results = bit_code_pool.search_knn_with_mode(&needle, k, KnnMode::Strict);
+            let found: Vec<(usize, usize)> = results.iter().map(|r| (r.distance(), r.idx())).collect();
+            assert_eq!(found, sorted[..k].to_vec());
+        }
+        let nearest = bit_code_pool.search_knn(&needle, 1)[0];
+        assert_eq!(nearest.estimated_cosine(), needle.view().estimated_cosine(bit_code_pool.get(nearest.idx()).unwrap()));
+    }
+
+    #[test]
+    fn search_knn_with_index() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2, 3], 64, 500));
+        for id in 0..3_000 { bit_code_pool.add(&random_string(4), id); }
+        bit_code_pool.index(8);
+        // Codes added after indexing are still found.
+        for id in 3_000..3_100 { bit_code_pool.add(&random_string(4), id); }
+        for i in 0..20 {
+            let needle = bit_code_pool.bit_code(&random_string(4));
+            for &k in &[1, 10, 100] {
+                assert_eq!(bit_code_pool.search_knn_with_index(&needle, k), bit_code_pool.search_knn(&needle, k));
+                assert_eq!(
+                    bit_code_pool.search_knn_with_index_and_mode(bit_code_pool.get(3_000 + i).unwrap(), k, KnnMode::Strict),
+                    bit_code_pool.search_knn_with_mode(bit_code_pool.get(3_000 + i).unwrap(), k, KnnMode::Strict));
+            }
+        }
+    }
+
+    #[test]
+    fn search_knn_with_wide_substrings() {
+        // Probing 32- and 64-bit substrings at increasing distances would take far longer than scanning, so the search falls back to a scan.
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2, 3], 256, 500));
+        for id in 0..5_000 { bit_code_pool.add(&random_string(6), id); }
+        for &bits in &[32, 64] {
+            bit_code_pool.index(bits);
+            let start = Instant::now();
+            for _ in 0..10 {
+                let needle = bit_code_pool.bit_code(&random_string(6));
+                assert_eq!(bit_code_pool.search_knn_with_index(&needle, 5), bit_code_pool.search_knn(&needle, 5));
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn index_auto() {
+        assert_eq!(auto_bits_per_index(0, 256, None), 1);
+        assert_eq!(auto_bits_per_index(1_000, 256, None), 10);
+        assert_eq!(auto_bits_per_index(1_000_000, 256, None), 20);
+        assert_eq!(auto_bits_per_index(1_000_000, 16, None), 16);
+        // Substrings are shortened to reach the target radius.
+        assert_eq!(auto_bits_per_index(1_000_000, 256, Some(12)), 20);
+        assert_eq!(auto_bits_per_index(1_000_000, 256, Some(31)), 8);
+        assert_eq!(auto_bits_per_index(1_000_000, 256, Some(1_000)), 1);
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![2, 3], 64, 500));
+        for id in 0..1_000 { bit_code_pool.add(&random_string(5), id); }
+        let parameters = bit_code_pool.index_auto();
+        assert_eq!(parameters.bits_per_index(), 10);
+        assert_eq!(parameters.num_indexes(), 7);
+        assert_eq!(parameters.max_searchable_radius(), bit_code_pool.index_max_searchable_radius());
+        let stats = bit_code_pool.index_stats();
+        assert_eq!(stats.num_values(), 1_000);
+        assert_eq!(stats.tables().len(), 7);
+        assert!(stats.tables().iter().all(|table| table.substring_length() <= 10 && table.max_bucket_size() >= 1));
+        let parameters = bit_code_pool.index_auto_for_radius(15);
+        assert_eq!(parameters.bits_per_index(), 4);
+        assert_eq!(bit_code_pool.index_max_searchable_radius(), 15);
+        let needle = bit_code_pool.get(0).unwrap();
+        let mut ids1 = bit_code_pool.search(needle, 15);
+        let mut ids2 = bit_code_pool.search_with_index(needle, 15).unwrap();
+        ids1.sort();
+        ids2.sort();
+        assert_eq!(ids1, ids2);
+    }
+
+    #[test]
+    fn self_join() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for id in 0..1_500 { bit_code_pool.add(&random_string(3), 1_000 + id); }
+        bit_code_pool.index(8);
+        for id in 1_500..1_600 { bit_code_pool.add(&random_string(3), 1_000 + id); }
+        let radius = 10;
+        let mut expected: Vec<(u64, u64, usize)> = Vec::new();
+        for i in 0..bit_code_pool.len() {
+            for j in (i + 1)..bit_code_pool.len() {
+                let d = bit_code_pool.code(i).hamming_distance(bit_code_pool.code(j));
+                if d <= radius { expected.push((1_000 + i as u64, 1_000 + j as u64, d)); }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(bit_code_pool.self_join(radius).collect::<Vec<(u64, u64, usize)>>(), expected);
+        let mut pairs: Vec<(u64, u64, usize)> = Vec::new();
+        bit_code_pool.self_join_for_each(radius, |a, b, d| pairs.push((a, b, d)));
+        assert_eq!(pairs, expected);
+        // Radii the index cannot answer are found by scanning.
+        let first_pairs = bit_code_pool.self_join(20).take_while(|&(a, _, _)| a == 1_000).count();
+        assert_eq!(first_pairs, bit_code_pool.search(bit_code_pool.get(0).unwrap(), 20).len() - 1);
+    }
+
+    #[test]
+    fn join() {
+        let encoding_options = || EncodingOptions::new(true, vec![1, 2], 64, 200);
+        let mut small = BitCodePool::new(encoding_options());
+        for id in 0..200 { small.add(&random_string(3), id); }
+        let mut large = BitCodePool::new(encoding_options());
+        for id in 0..2_000 { large.add(&random_string(3), 10_000 + id); }
+        large.index(8);
+        let radius = 10;
+        let mut expected: Vec<(u64, u64, usize)> = Vec::new();
+        for (i, code) in small.iter().enumerate() {
+            for (j, other_code) in large.iter().enumerate() {
+                let d = code.hamming_distance(other_code);
+                if d <= radius { expected.push((i as u64, 10_000 + j as u64, d)); }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(small.join(&large, radius), Ok(expected.clone()));
+        // Joining the other way round gives the same pairs, reversed and ordered by the larger pool.
+        let mut reversed: Vec<(u64, u64, usize)> = expected.iter().map(|&(a, b, d)| (b, a, d)).collect();
+        reversed.sort();
+        assert_eq!(large.join(&small, radius), Ok(reversed));
+        let pairs = small.join_knn(&large, 3).unwrap();
+        for i in 0..small.len() {
+            let expected: Vec<(u64, u64, usize)> = large.search_knn(small.get(i).unwrap(), 3).iter().map(|r| (i as u64, 10_000 + r.idx() as u64, r.distance())).collect();
+            let found: Vec<(u64, u64, usize)> = pairs.iter().cloned().filter(|&(a, _, _)| a == i as u64).collect();
+            assert_eq!(found, expected);
+        }
+        // Pools must be encoded the same way.
+        let other = BitCodePool::new(EncodingOptions::new(true, vec![1, 3], 64, 200));
+        assert_eq!(small.join(&other, radius), Err(BitCodeError::IncompatibleEncoding));
+        assert_eq!(small.join_knn(&other, 1), Err(BitCodeError::IncompatibleEncoding));
+    }
+
+    #[test]
+    fn dendrogram() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for id in 0..4_500 { bit_code_pool.add(&random_string(3), 100 + id); }
+        bit_code_pool.index_auto_for_radius(6);
+        let dendrogram = bit_code_pool.dendrogram(6);
+        assert_eq!(dendrogram.len(), 4_500);
+        assert!(dendrogram.merges().windows(2).all(|pair| pair[0].distance() <= pair[1].distance()));
+        let counts = dendrogram.cluster_counts();
+        assert_eq!(counts.len(), 7);
+        for &radius in &[0, 3, 6] {
+            let entity_sets = bit_code_pool.resolve_entities_with_method(radius, ClusteringMethod::SingleLinkage);
+            assert_eq!(counts[radius], (radius, entity_sets.len()));
+            assert_eq!(dendrogram.cut(radius), entity_sets);
+        }
+        assert!(dendrogram.cut_resolution(4).diff(&bit_code_pool.resolve(4, ClusteringMethod::SingleLinkage)).is_empty());
+        // At the full code length every pair links, so each task's links outgrow the pool and are reduced as they are found.
+        let mut small_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for id in 0..300 { small_pool.add(&random_string(3), id); }
+        let dendrogram = small_pool.dendrogram(64);
+        assert_eq!(dendrogram.merges().len(), 299);
+        assert_eq!(dendrogram.num_clusters(64), 1);
+    }
+
     #[test]
     fn resolve_entities() {
         // Make a bit code pool.
@@ -202,11 +1087,107 @@ mod tests {
         // Each entity set should have at least 1 member.
         let mut num_entities = 0;
         for entity_set in &entity_sets {
-            assert!(entity_set.len() >= 1);
+            assert!(!entity_set.is_empty());
             num_entities += entity_set.len();
         }
         // Number of resolved entities should equalnumber of bit codes in pool.
         assert_eq!(num_entities, bit_code_pool.len());
+        // Resolution is deterministic.
+        assert_eq!(bit_code_pool.resolve_entities(10), entity_sets);
+    }
+
+    #[test]
+    fn resolve_entities_methods() {
+        // Codes a and c are 4 apart, and b is 2 from each.
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 16, 100));
+        let codes = ["0000000000000000", "1100000000000000", "1111000000000000", "0000000011111111", "0000000011111110"];
+        for (id, code) in codes.iter().enumerate() { bit_code_pool.add_bit_code(&BitCode::from_bit_string(code), id as u64); }
+        assert_eq!(bit_code_pool.resolve_entities_with_method(2, ClusteringMethod::Star), vec![vec![0, 1], vec![2], vec![3, 4]]);
+        assert_eq!(bit_code_pool.resolve_entities_with_method(2, ClusteringMethod::SingleLinkage), vec![vec![0, 1, 2], vec![3, 4]]);
+        assert_eq!(bit_code_pool.resolve_entities_with_method(0, ClusteringMethod::SingleLinkage).len(), 5);
+    }
+
+    #[test]
+    fn resolve_entities_with_constraints() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 16, 100));
+        let codes = ["0000000000000000", "1100000000000000", "1111000000000000", "0000000011111111", "0000000011111110"];
+        for (id, code) in codes.iter().enumerate() { bit_code_pool.add_bit_code(&BitCode::from_bit_string(code), id as u64); }
+        // The link between codes 1 and 2 is refused.
+        let constraints = Constraints::new().cannot_link(0, 2).cannot_link(0, 99);
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(2, ClusteringMethod::SingleLinkage, &constraints), Ok(vec![vec![0, 1], vec![2], vec![3, 4]]));
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(2, ClusteringMethod::Star, &constraints), Ok(vec![vec![0, 1], vec![2], vec![3, 4]]));
+        // Must-linked codes are joined whatever their distance.
+        let constraints = Constraints::new().must_link(2, 3);
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(2, ClusteringMethod::SingleLinkage, &constraints), Ok(vec![vec![0, 1, 2, 3, 4]]));
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(2, ClusteringMethod::Star, &constraints), Ok(vec![vec![0, 1], vec![2, 3], vec![4]]));
+        let resolution = bit_code_pool.resolve_with_constraints(2, ClusteringMethod::Star, &constraints).unwrap();
+        assert_eq!(resolution.entity_of(3), Some(2));
+        // Contradictory constraints are rejected.
+        let constraints = Constraints::new().must_link(0, 1).must_link(1, 2).cannot_link(0, 2);
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(2, ClusteringMethod::Star, &constraints), Err(BitCodeError::ConstraintConflict { a: 0, b: 2 }));
+    }
+
+    #[test]
+    fn resolve_repeated_ids() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 16, 100));
+        // Id 7 is held by two codes far apart, each near another record.
+        let codes = [("0000000000000000", 7), ("1000000000000000", 1), ("0000000011111111", 2), ("0000000011111110", 7), ("1111111111111111", 3)];
+        for &(code, id) in &codes { bit_code_pool.add_bit_code(&BitCode::from_bit_string(code), id); }
+        // Star clustering treats the codes as a unit seeded by the first, so only its neighbours join.
+        assert_eq!(bit_code_pool.resolve_entities_with_method(1, ClusteringMethod::Star), vec![vec![0, 1, 3], vec![2], vec![4]]);
+        assert_eq!(bit_code_pool.resolve(1, ClusteringMethod::Star).members(1), Some(&[1, 7][..]));
+        assert_eq!(bit_code_pool.resolve_entities_with_method(1, ClusteringMethod::SingleLinkage), vec![vec![0, 1, 2, 3], vec![4]]);
+        let resolution = bit_code_pool.resolve(1, ClusteringMethod::SingleLinkage);
+        assert_eq!(resolution.members(1), Some(&[1, 2, 7][..]));
+        assert_eq!(resolution.entity_of(3), Some(3));
+        let dendrogram = bit_code_pool.dendrogram(2);
+        assert_eq!(dendrogram.cut(0), vec![vec![0, 3], vec![1], vec![2], vec![4]]);
+        // Constraints on the id apply to both of its codes, which stay together.
+        let constraints = Constraints::new().cannot_link(7, 2);
+        assert_eq!(bit_code_pool.resolve_entities_with_constraints(1, ClusteringMethod::SingleLinkage, &constraints), Ok(vec![vec![0, 1, 3], vec![2], vec![4]]));
+        let constraints = Constraints::new().must_link(7, 3);
+        let resolution = bit_code_pool.resolve_with_constraints(1, ClusteringMethod::Star, &constraints).unwrap();
+        assert_eq!(resolution.members(1), Some(&[1, 3, 7][..]));
+        let constraints = Constraints::new().cannot_link(7, 7);
+        assert_eq!(bit_code_pool.resolve_with_constraints(1, ClusteringMethod::Star, &constraints), Err(BitCodeError::ConstraintConflict { a: 7, b: 7 }));
+        assert!(dendrogram.cut_resolution(1).diff(&bit_code_pool.resolve(1, ClusteringMethod::SingleLinkage)).is_empty());
+    }
+
+    #[test]
+    fn resolve_entities_with_index() {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for id in 0..2_000 { bit_code_pool.add(&random_string(3), id); }
+        let radius = 10;
+        let star = bit_code_pool.resolve_entities_with_method(radius, ClusteringMethod::Star);
+        let single_linkage = bit_code_pool.resolve_entities_with_method(radius, ClusteringMethod::SingleLinkage);
+        assert!(single_linkage.len() < star.len());
+        // Indexed resolution, including codes added after the index was built, gives the same clusters.
+        let mut indexed_pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for (i, code) in bit_code_pool.iter().enumerate() {
+            indexed_pool.add_bit_code(&code.to_bit_code(), i as u64);
+            if i == 1_500 { indexed_pool.index(6); }
+        }
+        assert!(indexed_pool.index_pays_off(radius));
+        assert_eq!(indexed_pool.resolve_entities_with_method(radius, ClusteringMethod::Star), star);
+        assert_eq!(indexed_pool.resolve_entities_with_method(radius, ClusteringMethod::SingleLinkage), single_linkage);
+        let resolution = bit_code_pool.resolve(radius, ClusteringMethod::SingleLinkage);
+        assert_eq!(resolution.len(), single_linkage.len());
+        for entity_set in &single_linkage {
+            assert_eq!(resolution.members(entity_set[0] as u64), Some(&entity_set.iter().map(|&i| i as u64).collect::<Vec<u64>>()[..]));
+        }
+        assert!(resolution.diff(&indexed_pool.resolve(radius, ClusteringMethod::SingleLinkage)).is_empty());
+        // Four-bit substrings leave buckets so large that scanning is cheaper, with the same clusters.
+        indexed_pool.index(4);
+        assert!(!indexed_pool.index_pays_off(radius));
+        assert_eq!(indexed_pool.resolve_entities_with_method(radius, ClusteringMethod::Star), star);
+        // Brute-force single linkage agrees.
+        let mut union_find = UnionFind::new(bit_code_pool.len());
+        for i in 0..bit_code_pool.len() {
+            for j in 0..i {
+                if bit_code_pool.code(i).hamming_distance(bit_code_pool.code(j)) <= radius { union_find.union(i, j); }
+            }
+        }
+        assert_eq!(union_find.groups(), single_linkage);
     }
 
     #[bench]
@@ -223,7 +1204,24 @@ mod tests {
                 bit_code_pool.add(&string, id);
             }
             // Test.
-            assert_eq!(bit_code_pool.bit_codes.len(), num_bit_codes);
+            assert_eq!(bit_code_pool.len(), num_bit_codes);
         });
     }
+
+    #[bench]
+    fn search_knn_ties(b: &mut Bencher) {
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 16, 100));
+        for id in 0..10_000 { bit_code_pool.add(&random_string(1), id); }
+        let needle = bit_code_pool.bit_code("q");
+        b.iter(|| bit_code_pool.search_knn(&needle, 10));
+    }
+
+    #[bench]
+    fn search_bit_code_pool(b: &mut Bencher) {
+        // Random 256-bit codes, rather than encoded strings, so that setting up is cheap.
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::new(true, vec![1], 256, 1));
+        for id in 0..10_000 { bit_code_pool.add_bit_code(&BitCode::from_bit_string(&random_bit_string(256)), id); }
+        let needle = bit_code_pool.get(0).unwrap().to_bit_code();
+        b.iter(|| bit_code_pool.search(&needle, 64));
+    }
 }
@@ -0,0 +1,165 @@
+use encoding::string_to_bit_code;
+use encoding_options::EncodingOptions;
+
+
+/// Distributions of the Hamming distances between labelled matching and non-matching pairs of strings, from which a radius can be chosen. A pair is predicted to match when its distance is within the radius.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Calibration {
+    match_counts: Vec<usize>,               // Number of matching pairs at each distance from 0 to the number of bits.
+    non_match_counts: Vec<usize>,
+}
+
+
+/// A point on the ROC curve of a Calibration: how the labelled pairs are classified at a radius.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RocPoint {
+    radius: usize,
+    true_positives: usize,                  // Matching pairs within the radius.
+    false_positives: usize,                 // Non-matching pairs within the radius.
+    num_matches: usize,
+    num_non_matches: usize,
+}
+
+
+impl RocPoint {
+    pub fn radius(&self) -> usize { self.radius }
+
+    pub fn true_positives(&self) -> usize { self.true_positives }
+
+    pub fn false_positives(&self) -> usize { self.false_positives }
+
+    /// Fraction of pairs within the radius that match, or 1 if there are none.
+    pub fn precision(&self) -> f64 { ratio(self.true_positives, self.true_positives + self.false_positives) }
+
+    /// Fraction of matching pairs within the radius (the true positive rate), or 1 if there are none.
+    pub fn recall(&self) -> f64 { ratio(self.true_positives, self.num_matches) }
+
+    /// Fraction of non-matching pairs within the radius, or 0 if there are none.
+    pub fn false_positive_rate(&self) -> f64 {
+        if self.num_non_matches == 0 { 0.0 } else { self.false_positives as f64 / self.num_non_matches as f64 }
+    }
+}
+
+
+impl Calibration {
+    /// Encodes each labelled pair of strings, true for a match, and records the distance between their bit codes.
+    pub fn new<'a, I: IntoIterator<Item=(&'a str, &'a str, bool)>>(encoding_options: &EncodingOptions, labelled_pairs: I) -> Self {
+        let distances = labelled_pairs.into_iter().map(|(a, b, is_match)| {
+            let distance = string_to_bit_code(a, encoding_options).hamming_distance(&string_to_bit_code(b, encoding_options));
+            (distance, is_match)
+        });
+        Calibration::from_distances(encoding_options.num_bits(), distances)
+    }
+
+    /// Records labelled distances, true for a match, between bit codes of num_bits bits.
+    pub fn from_distances<I: IntoIterator<Item=(usize, bool)>>(num_bits: usize, labelled_distances: I) -> Self {
+        let mut match_counts: Vec<usize> = vec![0; num_bits + 1];
+        let mut non_match_counts: Vec<usize> = vec![0; num_bits + 1];
+        for (distance, is_match) in labelled_distances {
+            assert!(distance <= num_bits, "distance {} exceeds {} bits", distance, num_bits);
+            if is_match { match_counts[distance] += 1; } else { non_match_counts[distance] += 1; }
+        }
+        Calibration { match_counts, non_match_counts }
+    }
+
+    /// Number of matching pairs at each distance, from 0 to the number of bits.
+    pub fn match_histogram(&self) -> &[usize] {
+        &self.match_counts
+    }
+
+    /// Number of non-matching pairs at each distance, from 0 to the number of bits.
+    pub fn non_match_histogram(&self) -> &[usize] {
+        &self.non_match_counts
+    }
+
+    pub fn num_matches(&self) -> usize {
+        self.match_counts.iter().sum()
+    }
+
+    pub fn num_non_matches(&self) -> usize {
+        self.non_match_counts.iter().sum()
+    }
+
+    /// Returns how the pairs are classified at each radius, from 0 to the number of bits.
+    pub fn roc_curve(&self) -> Vec<RocPoint> {
+        let (num_matches, num_non_matches) = (self.num_matches(), self.num_non_matches());
+        let mut true_positives = 0;
+        let mut false_positives = 0;
+        self.match_counts.iter().zip(&self.non_match_counts).enumerate().map(|(radius, (&matches, &non_matches))| {
+            true_positives += matches;
+            false_positives += non_matches;
+            RocPoint { radius, true_positives, false_positives, num_matches, num_non_matches }
+        }).collect()
+    }
+
+    /// Returns the largest radius, and so the highest recall, at which the precision is at least the target. Radii within which there are no pairs are not considered. Returns None if no radius meets the target.
+    pub fn radius_for_precision(&self, target: f64) -> Option<usize> {
+        self.roc_curve().into_iter().rev()
+            .find(|point| point.true_positives + point.false_positives > 0 && point.precision() >= target)
+            .map(|point| point.radius)
+    }
+
+    /// Returns the smallest radius, and so the highest precision, at which the recall is at least the target. Returns None if no radius meets the target, which can only happen without matching pairs.
+    pub fn radius_for_recall(&self, target: f64) -> Option<usize> {
+        self.roc_curve().into_iter()
+            .find(|point| point.true_positives > 0 && point.recall() >= target)
+            .map(|point| point.radius)
+    }
+
+    /// Area under the ROC curve: the probability that a random matching pair is closer than a random non-matching pair, counting ties as half.
+    pub fn auc(&self) -> f64 {
+        let (num_matches, num_non_matches) = (self.num_matches(), self.num_non_matches());
+        if num_matches == 0 || num_non_matches == 0 { return 1.0; }
+        let mut farther_non_matches = num_non_matches;
+        let mut area = 0.0;
+        for (&matches, &non_matches) in self.match_counts.iter().zip(&self.non_match_counts) {
+            farther_non_matches -= non_matches;
+            area += matches as f64 * (farther_non_matches as f64 + non_matches as f64 / 2.0);
+        }
+        area / (num_matches as f64 * num_non_matches as f64)
+    }
+}
+
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 { 1.0 } else { numerator as f64 / denominator as f64 }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use encoding_options::EncodingOptions;
+    use super::Calibration;
+
+    #[test]
+    fn calibrate_radius() {
+        let labelled = vec![(1, true), (2, true), (2, true), (5, true), (2, false), (6, false), (7, false), (8, false)];
+        let calibration = Calibration::from_distances(8, labelled);
+        assert_eq!(calibration.match_histogram(), &[0, 1, 2, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(calibration.non_match_histogram(), &[0, 0, 1, 0, 0, 0, 1, 1, 1]);
+        let curve = calibration.roc_curve();
+        assert_eq!(curve.len(), 9);
+        assert_eq!((curve[2].true_positives(), curve[2].false_positives()), (3, 1));
+        assert_eq!(curve[2].precision(), 0.75);
+        assert_eq!(curve[5].recall(), 1.0);
+        assert_eq!(curve[6].false_positive_rate(), 0.5);
+        assert_eq!(calibration.radius_for_precision(1.0), Some(1));
+        assert_eq!(calibration.radius_for_precision(0.8), Some(5));
+        assert_eq!(calibration.radius_for_precision(0.1), Some(8));
+        assert_eq!(calibration.radius_for_recall(0.5), Some(2));
+        assert_eq!(calibration.radius_for_recall(1.0), Some(5));
+        // The non-match at distance 2 is closer than the match at 5 and ties those at 2.
+        assert_eq!(calibration.auc(), (4.0 * 4.0 - 1.0 - 2.0 * 0.5) / 16.0);
+    }
+
+    #[test]
+    fn calibrate_strings() {
+        let encoding_options = EncodingOptions::new(true, vec![1, 2], 64, 200);
+        let pairs = vec![("jonathan smith", "Jonathan Smith", true), ("jonathan smith", "zebediah quux", false)];
+        let calibration = Calibration::new(&encoding_options, pairs);
+        assert_eq!(calibration.match_histogram().len(), 65);
+        assert_eq!(calibration.match_histogram()[0], 1);
+        assert_eq!((calibration.num_matches(), calibration.num_non_matches()), (1, 1));
+        assert_eq!(calibration.radius_for_recall(1.0), Some(0));
+    }
+}
@@ -0,0 +1,131 @@
+use resolution::Resolution;
+use union_find::UnionFind;
+
+
+/// A single-linkage dendrogram over the bit codes of a pool: the merges that join clusters as the radius grows, in ascending order of distance, up to a maximum radius. Cutting it at any radius up to the maximum gives the same entity sets as single-linkage resolution at that radius.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dendrogram {
+    ids: Vec<u64>,                          // Ids of the codes, by position in the pool.
+    max_radius: usize,
+    merges: Vec<Merge>,
+}
+
+
+/// A merge of two clusters in a Dendrogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Merge {
+    distance: usize,
+    a: usize,
+    b: usize,
+    size: usize,
+}
+
+
+impl Merge {
+    /// Radius from which the clusters are merged: the distance between the closest pair of codes across them.
+    pub fn distance(&self) -> usize { self.distance }
+
+    /// Positions of that closest pair of codes, one in each cluster, the earlier first.
+    pub fn codes(&self) -> (usize, usize) { (self.a, self.b) }
+
+    /// Number of codes in the merged cluster.
+    pub fn size(&self) -> usize { self.size }
+}
+
+
+impl Dendrogram {
+    // Builds the dendrogram from links (distance, i, j) between codes, all within max_radius, by Kruskal's algorithm.
+    pub(crate) fn from_links(ids: Vec<u64>, max_radius: usize, links: Vec<(usize, usize, usize)>) -> Self {
+        let mut union_find = UnionFind::new(ids.len());
+        let mut sizes: Vec<usize> = vec![1; ids.len()];
+        let mut merges: Vec<Merge> = Vec::new();
+        for (distance, a, b) in spanning_links(ids.len(), links) {
+            let size = sizes[union_find.find(a)] + sizes[union_find.find(b)];
+            union_find.union(a, b);
+            sizes[union_find.find(a)] = size;
+            merges.push(Merge { distance, a, b, size });
+        }
+        Dendrogram { ids, max_radius, merges }
+    }
+
+    /// Merges in ascending order of distance.
+    pub fn merges(&self) -> &[Merge] {
+        &self.merges
+    }
+
+    /// Largest radius the dendrogram covers; cutting at a larger radius gives the clusters at this one.
+    pub fn max_radius(&self) -> usize {
+        self.max_radius
+    }
+
+    /// Number of codes clustered.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the entity sets of positions at the radius, as BitCodePool::resolve_entities_with_method with single linkage does.
+    pub fn cut(&self, radius: usize) -> Vec<Vec<usize>> {
+        let mut union_find = UnionFind::new(self.len());
+        for merge in self.merges.iter().take_while(|merge| merge.distance <= radius) {
+            union_find.union(merge.a, merge.b);
+        }
+        union_find.groups()
+    }
+
+    /// Returns the entities at the radius, identified by their smallest record id, as BitCodePool::resolve with single linkage does.
+    pub fn cut_resolution(&self, radius: usize) -> Resolution {
+        Resolution::from_entity_sets(self.cut(radius).into_iter().map(|entity_set| entity_set.into_iter().map(|i| self.ids[i]).collect()))
+    }
+
+    /// Returns the number of clusters at the radius.
+    pub fn num_clusters(&self, radius: usize) -> usize {
+        self.len() - self.merges.iter().take_while(|merge| merge.distance <= radius).count()
+    }
+
+    /// Returns the number of clusters at each radius from 0 to the maximum radius, as (radius, number of clusters).
+    pub fn cluster_counts(&self) -> Vec<(usize, usize)> {
+        let mut counts: Vec<(usize, usize)> = Vec::with_capacity(self.max_radius + 1);
+        let mut num_clusters = self.len();
+        let mut merges = self.merges.iter().peekable();
+        for radius in 0..(self.max_radius + 1) {
+            while merges.next_if(|merge| merge.distance <= radius).is_some() { num_clusters -= 1; }
+            counts.push((radius, num_clusters));
+        }
+        counts
+    }
+}
+
+
+// Returns the links (distance, i, j) between n codes that merge clusters when taken in ascending order, that is a minimum spanning forest, ties broken by position. The forest of a set of links together with more links is the forest of all of them, so links can be reduced as they are found.
+pub(crate) fn spanning_links(n: usize, mut links: Vec<(usize, usize, usize)>) -> Vec<(usize, usize, usize)> {
+    links.sort_unstable();
+    let mut union_find = UnionFind::new(n);
+    links.retain(|&(_, i, j)| union_find.union(i, j));
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dendrogram;
+
+    #[test]
+    fn dendrogram() {
+        // Codes 0-1 and 2-3 are 1 apart, 1-2 is 3 apart, 0-2 4 apart, and code 4 is isolated.
+        let links = vec![(3, 1, 2), (1, 0, 1), (4, 0, 2), (1, 2, 3)];
+        let dendrogram = Dendrogram::from_links(vec![10, 11, 12, 13, 14], 4, links);
+        let merges: Vec<(usize, (usize, usize), usize)> = dendrogram.merges().iter().map(|m| (m.distance(), m.codes(), m.size())).collect();
+        assert_eq!(merges, vec![(1, (0, 1), 2), (1, (2, 3), 2), (3, (1, 2), 4)]);
+        assert_eq!(dendrogram.cut(0), vec![vec![0], vec![1], vec![2], vec![3], vec![4]]);
+        assert_eq!(dendrogram.cut(2), vec![vec![0, 1], vec![2, 3], vec![4]]);
+        assert_eq!(dendrogram.cut(3), vec![vec![0, 1, 2, 3], vec![4]]);
+        assert_eq!(dendrogram.cut_resolution(3).members(10), Some(&[10, 11, 12, 13][..]));
+        assert_eq!(dendrogram.num_clusters(2), 3);
+        assert_eq!(dendrogram.cluster_counts(), vec![(0, 5), (1, 3), (2, 3), (3, 2), (4, 2)]);
+    }
+}
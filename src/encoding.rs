@@ -56,8 +56,8 @@ mod tests {
         let num_features = 500;
         let num_bits = 256;
         let encoding_options = EncodingOptions::new(downcase, ngram_lengths, num_bits, num_features);
-        let bit_code_1 = string_to_bit_code(&string, &encoding_options);
-        let bit_code_2 = string_to_bit_code(&string, &encoding_options);
+        let bit_code_1 = string_to_bit_code(string, &encoding_options);
+        let bit_code_2 = string_to_bit_code(string, &encoding_options);
         println!("{:?}", bit_code_1);
         println!("{:?}", bit_code_2);
         assert_eq!(bit_code_1.hamming_distance(&bit_code_2), 0);
@@ -19,26 +19,11 @@ impl EncodingOptions {
     pub fn new(downcase: bool, ngram_lengths: Vec<usize>, num_bits: usize, num_features: usize) -> Self {
         let random_projections = get_random_projection_vectors(num_features, num_bits);
         EncodingOptions {
-            downcase: downcase,
-            ngram_lengths: ngram_lengths,
-            num_bits: num_bits,
-            num_features: num_features,
-            random_projections: random_projections,
-        }
-    }
-
-    pub fn default() -> Self {
-        let downcase = true;
-        let ngram_lengths = vec![3, 4, 5, 6];
-        let num_bits = 256;
-        let num_features = 10000;
-        let random_projections = get_random_projection_vectors(num_features, num_bits);
-        EncodingOptions {
-            downcase: downcase,
-            ngram_lengths: ngram_lengths,
-            num_bits: num_bits,
-            num_features: num_features,
-            random_projections: random_projections,
+            downcase,
+            ngram_lengths,
+            num_bits,
+            num_features,
+            random_projections,
         }
     }
 
@@ -54,6 +39,11 @@ impl EncodingOptions {
     #[inline]
     pub fn num_features(&self) -> usize { self.num_features }
 
+    /// Returns whether strings are encoded the same way under both options, so that their bit codes can be compared. Random projections are determined by the other options, so are not compared.
+    pub fn is_compatible(&self, other: &EncodingOptions) -> bool {
+        self.downcase == other.downcase && self.ngram_lengths == other.ngram_lengths && self.num_bits == other.num_bits && self.num_features == other.num_features
+    }
+
     #[inline]
     pub fn project(&self, features: &HashMap<usize, f64, FastHasher>, bit: usize) -> bool {
         let mut acc: f64 = 0.0;
@@ -61,7 +51,18 @@ impl EncodingOptions {
         for (feature, frequency) in features.iter() {
             acc += vector[*feature] * frequency;
         }
-        if acc > 0.0 { true } else { false }
+        acc > 0.0
+    }
+}
+
+
+impl Default for EncodingOptions {
+    fn default() -> Self {
+        let downcase = true;
+        let ngram_lengths = vec![3, 4, 5, 6];
+        let num_bits = 256;
+        let num_features = 10000;
+        EncodingOptions::new(downcase, ngram_lengths, num_bits, num_features)
     }
 }
 
@@ -72,8 +73,8 @@ pub fn get_random_projection_vectors(num_features: usize, num_bits: usize) -> Ve
     let mut vectors: Vec<Vec<f64>> = Vec::new();//#(num_bits);
     for _ in 0..num_bits {
         let mut v: Vec<f64> = vec![0.0; num_features];
-        for i in 0..num_features {
-            v[i] = normal.ind_sample(&mut rng);
+        for x in v.iter_mut() {
+            *x = normal.ind_sample(&mut rng);
         }
         vectors.push(v);
     }
@@ -109,5 +110,8 @@ mod tests {
         for v in &encoding_options.random_projections {
             assert_eq!(v.len(), nf);
         }
+        assert!(encoding_options.is_compatible(&EncodingOptions::new(downcase, vec![3, 4, 5, 6], nb, nf)));
+        assert!(!encoding_options.is_compatible(&EncodingOptions::new(false, vec![3, 4, 5, 6], nb, nf)));
+        assert!(!encoding_options.is_compatible(&EncodingOptions::new(downcase, vec![3, 4, 5, 6], nb, nf + 1)));
     }
 }
@@ -0,0 +1,251 @@
+use bit_code::BitCode;
+use bit_code_pool::BitCodePool;
+use encoding_options::EncodingOptions;
+use resolution::Resolution;
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+use union_find::UnionFind;
+use utils::FastHasher;
+
+
+/// Resolves records into entities as they arrive. Each record joins the entity of every earlier record within the radius of it, merging those entities if there are several, or starts a new entity if there are none. The entities are the same as single-linkage resolution of all the records at once would give.
+#[derive(Debug)]
+pub struct EntityResolver {
+    pool: BitCodePool,                      // Bit codes of the records, in order of arrival.
+    radius: usize,
+    union_find: UnionFind,                  // Sets of pool positions forming an entity.
+    entity_ids: HashMap<usize, u64, FastHasher>, // Entity id of the representative of each set.
+    entities: BTreeMap<u64, Vec<u64>>,      // Ids of the records in each entity.
+    positions: HashMap<u64, usize, FastHasher>, // Pool position of each record id.
+    next_entity_id: u64,
+}
+
+
+/// The outcome of adding a record to an EntityResolver.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assignment {
+    entity_id: u64,
+    created: bool,
+    merged: Vec<u64>,
+}
+
+
+impl Assignment {
+    /// Id of the entity the record was assigned to.
+    pub fn entity_id(&self) -> u64 { self.entity_id }
+
+    /// Whether the record started a new entity.
+    pub fn created(&self) -> bool { self.created }
+
+    /// Ids of entities merged into the record's entity because the record was within the radius of them, in ascending order. These ids are no longer used.
+    pub fn merged(&self) -> &[u64] { &self.merged }
+}
+
+
+impl EntityResolver {
+    pub fn new(encoding_options: EncodingOptions, radius: usize) -> Self {
+        EntityResolver {
+            pool: BitCodePool::new(encoding_options),
+            radius,
+            union_find: UnionFind::new(0),
+            entity_ids: HashMap::default(),
+            entities: BTreeMap::new(),
+            positions: HashMap::default(),
+            next_entity_id: 0,
+        }
+    }
+
+    /// Adds a record, assigning it to an entity. When entities merge the oldest entity id is kept. A record with the id of an earlier record joins that record's entity, whatever their distance.
+    pub fn add(&mut self, string: &str, id: u64) -> Assignment {
+        let bit_code = self.pool.bit_code(string);
+        self.add_bit_code(&bit_code, id)
+    }
+
+    fn add_bit_code(&mut self, bit_code: &BitCode, id: u64) -> Assignment {
+        let mut neighbors = self.pool.neighbors_from(bit_code.view(), 0, self.radius);
+        self.pool.add_bit_code(bit_code, id);
+        let position = self.union_find.add();
+        // A repeated id links to the first record with it, which keeps its position, and is listed once among the entity's records.
+        let first_position = *self.positions.entry(id).or_insert(position);
+        let is_repeat = first_position != position;
+        if is_repeat { neighbors.push((first_position, 0)); }
+        // Find the distinct entities of the neighbours, oldest first.
+        let mut roots: Vec<usize> = neighbors.iter().map(|&(j, _)| self.union_find.find(j)).collect();
+        roots.sort_unstable();
+        roots.dedup();
+        let mut neighbor_entities: Vec<u64> = roots.iter().map(|root| self.entity_ids[root]).collect();
+        neighbor_entities.sort_unstable();
+        let assignment = if neighbor_entities.is_empty() {
+            let entity_id = self.next_entity_id;
+            self.next_entity_id += 1;
+            self.entity_ids.insert(position, entity_id);
+            self.entities.insert(entity_id, vec![id]);
+            Assignment { entity_id, created: true, merged: Vec::new() }
+        } else {
+            let entity_id = neighbor_entities[0];
+            for &root in &roots {
+                self.entity_ids.remove(&root);
+                self.union_find.union(position, root);
+            }
+            self.entity_ids.insert(self.union_find.find(position), entity_id);
+            let mut members = self.entities.remove(&entity_id).unwrap();
+            for merged_id in &neighbor_entities[1..] {
+                let mut merged_members = self.entities.remove(merged_id).unwrap();
+                // Append the smaller member list to the larger.
+                if merged_members.len() > members.len() { ::std::mem::swap(&mut members, &mut merged_members); }
+                members.extend(merged_members);
+            }
+            if !is_repeat { members.push(id); }
+            self.entities.insert(entity_id, members);
+            Assignment { entity_id, created: false, merged: neighbor_entities[1..].to_vec() }
+        };
+        self.reindex_if_needed();
+        assignment
+    }
+
+    // Rebuild the pool's multi-index once the records added since it was built, which are scanned linearly, outnumber those in it.
+    fn reindex_if_needed(&mut self) {
+        let num_unindexed = self.pool.len() - self.pool.num_indexed();
+        if num_unindexed >= max(REINDEX_MIN_UNINDEXED, self.pool.num_indexed()) {
+            self.pool.index_auto_for_radius(self.radius);
+        }
+    }
+
+    /// Returns an iterator over the entities, as their ids and the ids of their records, in ascending order of entity id. Records within an entity are in no particular order.
+    pub fn entities(&self) -> impl Iterator<Item=(u64, &[u64])> {
+        self.entities.iter().map(|(&entity_id, members)| (entity_id, &members[..]))
+    }
+
+    /// Returns the id of the entity a record belongs to.
+    pub fn entity_of(&self, id: u64) -> Option<u64> {
+        let position = *self.positions.get(&id)?;
+        Some(self.entity_ids[&self.union_find.root(position)])
+    }
+
+    /// Returns the ids of the records in an entity.
+    pub fn members(&self, entity_id: u64) -> Option<&[u64]> {
+        self.entities.get(&entity_id).map(|members| &members[..])
+    }
+
+    /// Returns the current entities as a Resolution, in which entities are identified by their smallest record id rather than by the ids allocated here.
+    pub fn resolution(&self) -> Resolution {
+        Resolution::from_entity_sets(self.entities.values().cloned())
+    }
+
+    /// Returns the map from record ids to the ids of their entities.
+    pub fn membership(&self) -> HashMap<u64, u64, FastHasher> {
+        let mut membership: HashMap<u64, u64, FastHasher> = HashMap::with_capacity_and_hasher(self.len(), FastHasher::default());
+        for (&entity_id, members) in &self.entities {
+            for &id in members { membership.insert(id, entity_id); }
+        }
+        membership
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty()
+    }
+
+    /// Number of records added, counting each version of a repeated id.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
+
+    #[inline]
+    pub fn num_entities(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// The pool holding the records' bit codes, in order of arrival.
+    pub fn pool(&self) -> &BitCodePool {
+        &self.pool
+    }
+
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+}
+
+
+// Minimum number of unindexed records before the multi-index is rebuilt.
+const REINDEX_MIN_UNINDEXED: usize = 1_000;
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code::BitCode;
+    use bit_code_pool::ClusteringMethod;
+    use encoding_options::EncodingOptions;
+    use super::EntityResolver;
+    use utils::random_string;
+
+    #[test]
+    fn merges_bridged_entities() {
+        let mut resolver = EntityResolver::new(EncodingOptions::new(true, vec![1], 8, 100), 2);
+        let first = resolver.add_bit_code(&BitCode::from_bit_string("00000000"), 10);
+        assert!(first.created());
+        assert_eq!(first.entity_id(), 0);
+        let second = resolver.add_bit_code(&BitCode::from_bit_string("11110000"), 20);
+        assert_eq!((second.entity_id(), second.created()), (1, true));
+        let third = resolver.add_bit_code(&BitCode::from_bit_string("11111100"), 30);
+        assert_eq!((third.entity_id(), third.created()), (1, false));
+        assert_eq!(resolver.num_entities(), 2);
+        // A record 2 bits from each entity bridges them, keeping the older id.
+        let bridge = resolver.add_bit_code(&BitCode::from_bit_string("11000000"), 40);
+        assert_eq!(bridge.entity_id(), 0);
+        assert_eq!(bridge.merged(), &[1]);
+        assert_eq!(resolver.num_entities(), 1);
+        assert_eq!(resolver.members(1), None);
+        let mut members = resolver.members(0).unwrap().to_vec();
+        members.sort();
+        assert_eq!(members, vec![10, 20, 30, 40]);
+        assert_eq!(resolver.entity_of(30), Some(0));
+        assert_eq!(resolver.entity_of(50), None);
+        assert_eq!(resolver.membership().len(), 4);
+    }
+
+    #[test]
+    fn repeated_ids() {
+        let mut resolver = EntityResolver::new(EncodingOptions::new(true, vec![1], 8, 100), 2);
+        resolver.add_bit_code(&BitCode::from_bit_string("00000000"), 10);
+        resolver.add_bit_code(&BitCode::from_bit_string("11111111"), 20);
+        // A second version of record 10, far from the first, joins its entity.
+        let repeat = resolver.add_bit_code(&BitCode::from_bit_string("11110000"), 10);
+        assert_eq!((repeat.entity_id(), repeat.created()), (0, false));
+        assert_eq!(resolver.members(0), Some(&[10][..]));
+        // A record near the second version bridges to it, merging record 20's entity into record 10's.
+        let bridge = resolver.add_bit_code(&BitCode::from_bit_string("11111100"), 30);
+        assert_eq!(bridge.entity_id(), 0);
+        assert_eq!(bridge.merged(), &[1]);
+        let mut members = resolver.members(0).unwrap().to_vec();
+        members.sort();
+        assert_eq!(members, vec![10, 20, 30]);
+        assert_eq!(resolver.len(), 4);
+        assert_eq!(resolver.entity_of(10), Some(0));
+        assert_eq!(resolver.membership().len(), 3);
+    }
+
+    #[test]
+    fn matches_batch_resolution() {
+        let radius = 6;
+        let mut resolver = EntityResolver::new(EncodingOptions::new(true, vec![1, 2], 64, 200), radius);
+        // Enough records to trigger reindexing.
+        for id in 0..3_000 { resolver.add(&random_string(3), 100 + id); }
+        assert!(resolver.pool().num_indexed() > 0);
+        let mut entities: Vec<Vec<u64>> = resolver.entities().map(|(_, members)| {
+            let mut members = members.to_vec();
+            members.sort();
+            members
+        }).collect();
+        entities.sort();
+        let batch: Vec<Vec<u64>> = resolver.pool().resolve_entities_with_method(radius, ClusteringMethod::SingleLinkage).into_iter()
+            .map(|entity_set| entity_set.into_iter().map(|i| 100 + i as u64).collect())
+            .collect();
+        assert_eq!(entities, batch);
+        assert!(resolver.resolution().diff(&resolver.pool().resolve(radius, ClusteringMethod::SingleLinkage)).is_empty());
+        for (entity_id, members) in resolver.entities() {
+            for &id in members { assert_eq!(resolver.entity_of(id), Some(entity_id)); }
+        }
+    }
+}
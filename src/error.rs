@@ -0,0 +1,39 @@
+use std::error::Error;
+use std::fmt;
+
+
+/// Errors raised by checked operations on bit codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitCodeError {
+    /// Two bit codes that must be the same length were not.
+    LengthMismatch { left: usize, right: usize },
+    /// A bit code was too long to fit in a fixed-width code.
+    CapacityExceeded { num_bits: usize, capacity: usize },
+    /// Bit codes from pools with different encoding options were compared.
+    IncompatibleEncoding,
+    /// Must-link constraints required two records that cannot link to be in the same entity.
+    ConstraintConflict { a: u64, b: u64 },
+}
+
+
+impl fmt::Display for BitCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BitCodeError::LengthMismatch { left, right } => {
+                write!(f, "bit codes have different lengths ({} and {})", left, right)
+            },
+            BitCodeError::CapacityExceeded { num_bits, capacity } => {
+                write!(f, "{} bits do not fit in a {}-bit code", num_bits, capacity)
+            },
+            BitCodeError::IncompatibleEncoding => {
+                write!(f, "bit codes were encoded with different encoding options")
+            },
+            BitCodeError::ConstraintConflict { a, b } => {
+                write!(f, "records {} and {} cannot link but are linked by must-link constraints", a, b)
+            },
+        }
+    }
+}
+
+
+impl Error for BitCodeError {}
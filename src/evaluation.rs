@@ -0,0 +1,220 @@
+use bit_code_pool::{BitCodePool, ClusteringMethod};
+use resolution::Resolution;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use utils::FastHasher;
+
+
+/// Pairwise precision, recall and F1 of predicted matching pairs of records against gold entities. A pair is correct when both records are in the same gold entity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PairwiseScores {
+    num_correct: usize,                     // Predicted pairs in the same gold entity.
+    num_predicted: usize,
+    num_gold: usize,                        // Pairs of records in the same gold entity.
+}
+
+
+impl PairwiseScores {
+    pub fn num_correct(&self) -> usize { self.num_correct }
+
+    pub fn num_predicted(&self) -> usize { self.num_predicted }
+
+    pub fn num_gold(&self) -> usize { self.num_gold }
+
+    /// Fraction of predicted pairs that are correct, or 1 if no pairs were predicted.
+    pub fn precision(&self) -> f64 { ratio(self.num_correct, self.num_predicted) }
+
+    /// Fraction of gold pairs that were predicted, or 1 if there are no gold pairs.
+    pub fn recall(&self) -> f64 { ratio(self.num_correct, self.num_gold) }
+
+    pub fn f1(&self) -> f64 { f1(self.precision(), self.recall()) }
+}
+
+
+/// Scores of predicted entities against gold entities: pairwise, B-cubed and purity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Evaluation {
+    pairwise: PairwiseScores,
+    bcubed_precision: f64,
+    bcubed_recall: f64,
+    purity: f64,
+}
+
+
+impl Evaluation {
+    /// Scores of the pairs of records in the same predicted entity.
+    pub fn pairwise(&self) -> PairwiseScores { self.pairwise }
+
+    /// Mean over records of the fraction of their predicted entity that is in their gold entity.
+    pub fn bcubed_precision(&self) -> f64 { self.bcubed_precision }
+
+    /// Mean over records of the fraction of their gold entity that is in their predicted entity.
+    pub fn bcubed_recall(&self) -> f64 { self.bcubed_recall }
+
+    pub fn bcubed_f1(&self) -> f64 { f1(self.bcubed_precision, self.bcubed_recall) }
+
+    /// Fraction of records in the gold entity most common in their predicted entity.
+    pub fn purity(&self) -> f64 { self.purity }
+}
+
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "precision={:.4} recall={:.4} f1={:.4} bcubed_precision={:.4} bcubed_recall={:.4} bcubed_f1={:.4} purity={:.4}",
+            self.pairwise.precision(), self.pairwise.recall(), self.pairwise.f1(),
+            self.bcubed_precision, self.bcubed_recall, self.bcubed_f1(), self.purity)
+    }
+}
+
+
+/// Scores predicted entities against gold entities. A record in only one of the resolutions is treated as an entity of its own in the other.
+pub fn evaluate(predicted: &Resolution, gold: &Resolution) -> Evaluation {
+    // An entity's id is one of its records, so an unresolved record's own id cannot be confused with an entity's.
+    let mut ids: HashSet<u64, FastHasher> = HashSet::default();
+    for (_, members) in predicted.entities().chain(gold.entities()) { ids.extend(members); }
+    let mut overlaps: HashMap<(u64, u64), usize, FastHasher> = HashMap::default();
+    let mut predicted_sizes: HashMap<u64, usize, FastHasher> = HashMap::default();
+    let mut gold_sizes: HashMap<u64, usize, FastHasher> = HashMap::default();
+    for &id in &ids {
+        let predicted_id = predicted.entity_of(id).unwrap_or(id);
+        let gold_id = gold.entity_of(id).unwrap_or(id);
+        *overlaps.entry((predicted_id, gold_id)).or_insert(0) += 1;
+        *predicted_sizes.entry(predicted_id).or_insert(0) += 1;
+        *gold_sizes.entry(gold_id).or_insert(0) += 1;
+    }
+    let num_records = ids.len();
+    let mut bcubed_precision = 0.0;
+    let mut bcubed_recall = 0.0;
+    let mut largest_overlaps: HashMap<u64, usize, FastHasher> = HashMap::default();
+    let mut num_correct = 0;
+    for (&(predicted_id, gold_id), &overlap) in &overlaps {
+        num_correct += num_pairs(overlap);
+        let weight = (overlap * overlap) as f64;
+        bcubed_precision += weight / predicted_sizes[&predicted_id] as f64;
+        bcubed_recall += weight / gold_sizes[&gold_id] as f64;
+        let largest = largest_overlaps.entry(predicted_id).or_insert(0);
+        if overlap > *largest { *largest = overlap; }
+    }
+    let pairwise = PairwiseScores {
+        num_correct,
+        num_predicted: predicted_sizes.values().map(|&size| num_pairs(size)).sum(),
+        num_gold: gold_sizes.values().map(|&size| num_pairs(size)).sum(),
+    };
+    if num_records == 0 {
+        return Evaluation { pairwise, bcubed_precision: 1.0, bcubed_recall: 1.0, purity: 1.0 };
+    }
+    Evaluation {
+        pairwise,
+        bcubed_precision: bcubed_precision / num_records as f64,
+        bcubed_recall: bcubed_recall / num_records as f64,
+        purity: largest_overlaps.values().sum::<usize>() as f64 / num_records as f64,
+    }
+}
+
+
+/// Scores predicted matching pairs of record ids, such as those from BitCodePool::self_join, against gold entities. Pairs are unordered, and repeated pairs and pairs of a record with itself are ignored.
+pub fn evaluate_pairs<I: IntoIterator<Item=(u64, u64)>>(pairs: I, gold: &Resolution) -> PairwiseScores {
+    let pairs: HashSet<(u64, u64), FastHasher> = pairs.into_iter()
+        .filter(|&(a, b)| a != b)
+        .map(|(a, b)| if a < b { (a, b) } else { (b, a) })
+        .collect();
+    let num_correct = pairs.iter().filter(|&&(a, b)| {
+        match (gold.entity_of(a), gold.entity_of(b)) {
+            (Some(entity_a), Some(entity_b)) => entity_a == entity_b,
+            _ => false,
+        }
+    }).count();
+    PairwiseScores {
+        num_correct,
+        num_predicted: pairs.len(),
+        num_gold: gold.entities().map(|(_, members)| num_pairs(members.len())).sum(),
+    }
+}
+
+
+/// Resolves the pool at each radius and scores the entities against gold entities, tracing a precision-recall curve. Single linkage builds one dendrogram up to the largest radius and cuts it, rather than resolving at every radius.
+pub fn radius_sweep(pool: &BitCodePool, gold: &Resolution, radii: &[usize], method: ClusteringMethod) -> Vec<(usize, Evaluation)> {
+    match method {
+        ClusteringMethod::Star => {
+            radii.iter().map(|&radius| (radius, evaluate(&pool.resolve(radius, method), gold))).collect()
+        },
+        ClusteringMethod::SingleLinkage => {
+            let dendrogram = pool.dendrogram(radii.iter().cloned().max().unwrap_or(0));
+            radii.iter().map(|&radius| (radius, evaluate(&dendrogram.cut_resolution(radius), gold))).collect()
+        },
+    }
+}
+
+
+// Number of unordered pairs of n things.
+fn num_pairs(n: usize) -> usize {
+    n * n.saturating_sub(1) / 2
+}
+
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 { 1.0 } else { numerator as f64 / denominator as f64 }
+}
+
+
+fn f1(precision: f64, recall: f64) -> f64 {
+    if precision + recall == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code_pool::{BitCodePool, ClusteringMethod};
+    use encoding_options::EncodingOptions;
+    use resolution::Resolution;
+    use super::{evaluate, evaluate_pairs, radius_sweep};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn evaluate_entities() {
+        let gold = Resolution::from_entity_sets(vec![vec![1, 2, 3], vec![4, 5]]);
+        let predicted = Resolution::from_entity_sets(vec![vec![1, 2], vec![3, 4, 5], vec![6]]);
+        let evaluation = evaluate(&predicted, &gold);
+        let pairwise = evaluation.pairwise();
+        assert_eq!((pairwise.num_correct(), pairwise.num_predicted(), pairwise.num_gold()), (2, 4, 4));
+        assert_close(pairwise.precision(), 0.5);
+        assert_close(pairwise.f1(), 0.5);
+        // Record 6 is its own gold entity.
+        assert_close(evaluation.bcubed_precision(), (1.0 + 1.0 + 1.0 / 3.0 + 2.0 / 3.0 + 2.0 / 3.0 + 1.0) / 6.0);
+        assert_close(evaluation.bcubed_recall(), (2.0 / 3.0 + 2.0 / 3.0 + 1.0 / 3.0 + 1.0 + 1.0 + 1.0) / 6.0);
+        assert_close(evaluation.purity(), 5.0 / 6.0);
+        let perfect = evaluate(&gold, &gold);
+        assert_close(perfect.pairwise().f1(), 1.0);
+        assert_close(perfect.bcubed_f1(), 1.0);
+        assert_close(perfect.purity(), 1.0);
+    }
+
+    #[test]
+    fn evaluate_matching_pairs() {
+        let gold = Resolution::from_entity_sets(vec![vec![1, 2, 3], vec![4, 5]]);
+        let scores = evaluate_pairs(vec![(2, 1), (1, 2), (1, 4), (5, 4), (6, 6), (6, 7)], &gold);
+        assert_eq!((scores.num_correct(), scores.num_predicted(), scores.num_gold()), (2, 4, 4));
+        assert_close(scores.recall(), 0.5);
+    }
+
+    #[test]
+    fn sweep() {
+        let mut pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        let names = ["jonathan smith", "jonathon smith", "mary jones", "mary jone", "peter parker", "zebediah quux"];
+        for (id, name) in names.iter().enumerate() { pool.add(name, id as u64); }
+        let gold = Resolution::from_entity_sets(vec![vec![0, 1], vec![2, 3], vec![4], vec![5]]);
+        let radii = [0, 8, 64];
+        for &method in &[ClusteringMethod::Star, ClusteringMethod::SingleLinkage] {
+            let curve = radius_sweep(&pool, &gold, &radii, method);
+            assert_eq!(curve.len(), 3);
+            assert_eq!(curve[0].1.pairwise().num_predicted(), 0);
+            // Everything is within 64 bits of everything else.
+            assert_eq!(curve[2].1.pairwise().num_predicted(), 15);
+            assert_close(curve[2].1.pairwise().recall(), 1.0);
+            assert!(curve.windows(2).all(|pair| pair[0].1.pairwise().recall() <= pair[1].1.pairwise().recall()));
+        }
+    }
+}
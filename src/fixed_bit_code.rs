@@ -0,0 +1,382 @@
+use bit_code::{BitCode, BitCodeRef, Ones};
+use encoding::string_to_bit_code;
+use encoding_options::EncodingOptions;
+use error::BitCodeError;
+use similarity::CosineEstimate;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use utils::num_blocks_needed;
+
+
+// A FixedBitCode holds up to 64 * WORDS bits in an inline array of u64 blocks, so it can be copied and stored contiguously without a heap allocation per code. Unused bits beyond len() are always unset. It has the same API as BitCode.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedBitCode<const WORDS: usize> {
+    blocks: [u64; WORDS],
+    num_bits: usize,
+}
+
+
+pub type FixedBitCode64 = FixedBitCode<1>;
+pub type FixedBitCode128 = FixedBitCode<2>;
+pub type FixedBitCode256 = FixedBitCode<4>;
+pub type FixedBitCode512 = FixedBitCode<8>;
+
+
+impl<const WORDS: usize> FixedBitCode<WORDS> {
+
+    /// Maximum number of bits a code of this width can hold.
+    pub const CAPACITY: usize = 64 * WORDS;
+
+    /// Returns a code of num_bits unset bits. Panics if num_bits exceeds the capacity of the code.
+    pub fn new(num_bits: usize) -> Self {
+        assert!(num_bits <= Self::CAPACITY, "{} bits do not fit in a {}-bit code", num_bits, Self::CAPACITY);
+        FixedBitCode { blocks: [0; WORDS], num_bits }
+    }
+
+    /// Returns a code from packed u64 blocks, laid out as returned by blocks(). Panics if num_bits exceeds the capacity of the code; any bits beyond num_bits are cleared.
+    pub fn from_blocks(blocks: [u64; WORDS], num_bits: usize) -> Self {
+        let mut bit_code = Self::new(num_bits);
+        bit_code.blocks = blocks;
+        bit_code.fix_last_block();
+        bit_code
+    }
+
+    pub fn from_bools(bools: &[bool]) -> Self {
+        let mut bit_code = Self::new(bools.len());
+        for (i, b) in bools.iter().enumerate() {
+            bit_code.set(i, *b);
+        }
+        bit_code
+    }
+
+    pub fn from_bit_string(string: &str) -> Self {
+        let mut bit_code = Self::new(string.len());
+        for (i, c) in string.chars().enumerate() {
+            bit_code.set(i, c == '1');
+        }
+        bit_code
+    }
+
+    /// Encodes a string as in BitCode::from_string. Panics if the encoding options produce more bits than the code can hold.
+    pub fn from_string(string: &str, encoding_options: &EncodingOptions) -> Self {
+        match Self::try_from(&string_to_bit_code(string, encoding_options)) {
+            Ok(fixed) => fixed,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns the bitwise majority vote of the codes. See BitCode::majority.
+    pub fn majority(bit_codes: &[Self]) -> Option<Self> {
+        let first = bit_codes.first()?;
+        let mut counts: Vec<usize> = vec![0; first.len()];
+        for bit_code in bit_codes {
+            assert_same_len(first, bit_code);
+            for i in bit_code.ones() { counts[i] += 1; }
+        }
+        let n = bit_codes.len();
+        let mut majority = Self::new(first.len());
+        for (i, c) in counts.iter().enumerate() {
+            if 2 * c > n { majority.set(i, true); }
+        }
+        Some(majority)
+    }
+
+    /// Returns the u64 blocks holding the bits; bit i is bit i % 64 of block i / 64.
+    #[inline]
+    pub fn blocks(&self) -> &[u64; WORDS] {
+        &self.blocks
+    }
+
+    #[inline]
+    pub fn count_ones(&self) -> usize {
+        let mut n = 0;
+        for block in &self.blocks { n += block.count_ones(); }
+        n as usize
+    }
+
+    /// Returns the positions at which this code and the other differ, in ascending order. Panics if the codes differ in length.
+    pub fn diff_positions(&self, other: &Self) -> Vec<usize> {
+        (*self ^ *other).ones().collect()
+    }
+
+    #[inline]
+    pub fn get(&self, bit_number: usize) -> Option<bool> {
+        if bit_number >= self.num_bits { return None; }
+        Some((self.blocks[bit_number / 64] >> (bit_number % 64)) & 1 == 1)
+    }
+
+    /// Returns the number of bits at which the codes differ. As for BitCode, a shorter code is treated as if it were padded with unset bits.
+    #[inline]
+    pub fn hamming_distance(&self, other: &Self) -> usize {
+        let mut d: u32 = 0;
+        for i in 0..WORDS {
+            d += (self.blocks[i] ^ other.blocks[i]).count_ones();
+        }
+        d as usize
+    }
+
+    /// Returns the Hamming distance to the other code if it is <= radius, and None otherwise, stopping as soon as the radius is exceeded.
+    #[inline]
+    pub fn within(&self, other: &Self, radius: usize) -> Option<usize> {
+        let mut d: usize = 0;
+        for i in 0..WORDS {
+            d += (self.blocks[i] ^ other.blocks[i]).count_ones() as usize;
+            if d > radius { return None; }
+        }
+        Some(d)
+    }
+
+    /// Returns the Hamming distance between the codes, or an error if they are not the same length.
+    #[inline]
+    pub fn try_hamming_distance(&self, other: &Self) -> Result<usize, BitCodeError> {
+        if self.len() != other.len() {
+            return Err(BitCodeError::LengthMismatch { left: self.len(), right: other.len() });
+        }
+        Ok(self.hamming_distance(other))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.num_bits == 0
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Returns the substrings used as multi-index keys. See BitCodeRef::index_values.
+    pub fn index_values(&self, index_length: usize) -> Vec<u64> {
+        self.view().index_values(index_length)
+    }
+
+    /// Returns the length bits (at most 64) starting at bit start, packed into a u64. See BitCode::substring.
+    #[inline]
+    pub fn substring(&self, start: usize, length: usize) -> u64 {
+        self.view().substring(start, length)
+    }
+
+    /// Estimates the cosine similarity of the feature vectors the codes were projected from. See BitCode::estimated_cosine.
+    pub fn estimated_cosine(&self, other: &Self) -> CosineEstimate {
+        self.view().estimated_cosine(other.view())
+    }
+
+    /// Returns an iterator over the positions of set bits, in ascending order.
+    pub fn ones(&self) -> Ones<'_> {
+        Ones::new(&self.blocks)
+    }
+
+    /// Returns a borrowed view of the code, so it can be compared with codes in a BitCodePool.
+    #[inline]
+    pub fn view(&self) -> BitCodeRef<'_> {
+        BitCodeRef::new(&self.blocks[..num_blocks_needed(self.num_bits)], self.num_bits)
+    }
+
+    /// Sets a bit. Panics if bit_number is out of bounds.
+    #[inline]
+    pub fn set(&mut self, bit_number: usize, value: bool) {
+        assert!(bit_number < self.num_bits, "bit {} out of bounds for a code of length {}", bit_number, self.num_bits);
+        let mask = 1u64 << (bit_number % 64);
+        if value { self.blocks[bit_number / 64] |= mask; } else { self.blocks[bit_number / 64] &= !mask; }
+    }
+
+    // Clear any bits beyond len() in the last block.
+    #[inline]
+    fn fix_last_block(&mut self) {
+        for i in 0..WORDS {
+            let start = i * 64;
+            if start >= self.num_bits {
+                self.blocks[i] = 0;
+            } else if self.num_bits - start < 64 {
+                self.blocks[i] &= (1u64 << (self.num_bits - start)) - 1;
+            }
+        }
+    }
+}
+
+
+impl<const WORDS: usize> fmt::Debug for FixedBitCode<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bits: String = (0..self.num_bits).map(|i| if self.get(i) == Some(true) { '1' } else { '0' }).collect();
+        write!(f, "FixedBitCode {{ bits: {} }}", bits)
+    }
+}
+
+
+impl<'a, const WORDS: usize> From<&'a FixedBitCode<WORDS>> for BitCode {
+    fn from(fixed: &'a FixedBitCode<WORDS>) -> BitCode {
+        fixed.view().to_bit_code()
+    }
+}
+
+
+impl<'a, const WORDS: usize> From<&'a FixedBitCode<WORDS>> for BitCodeRef<'a> {
+    fn from(fixed: &'a FixedBitCode<WORDS>) -> BitCodeRef<'a> {
+        fixed.view()
+    }
+}
+
+
+impl<'a, const WORDS: usize> TryFrom<&'a BitCode> for FixedBitCode<WORDS> {
+    type Error = BitCodeError;
+
+    fn try_from(bit_code: &'a BitCode) -> Result<Self, BitCodeError> {
+        if bit_code.len() > Self::CAPACITY {
+            return Err(BitCodeError::CapacityExceeded { num_bits: bit_code.len(), capacity: Self::CAPACITY });
+        }
+        let mut fixed = Self::new(bit_code.len());
+        let blocks = bit_code.blocks();
+        fixed.blocks[..blocks.len()].copy_from_slice(blocks);
+        Ok(fixed)
+    }
+}
+
+
+#[inline]
+fn assert_same_len<const WORDS: usize>(a: &FixedBitCode<WORDS>, b: &FixedBitCode<WORDS>) {
+    assert!(a.len() == b.len(), "bit codes have different lengths ({} and {})", a.len(), b.len());
+}
+
+
+// Operators work block-wise on copies, so are implemented for values and references alike.
+macro_rules! impl_fixed_binary_op {
+    ($op:ident, $method:ident, $assign_op:ident, $assign_method:ident, $operator:tt) => {
+        impl<const WORDS: usize> $assign_op for FixedBitCode<WORDS> {
+            fn $assign_method(&mut self, other: FixedBitCode<WORDS>) {
+                assert_same_len(self, &other);
+                for i in 0..WORDS { self.blocks[i] = self.blocks[i] $operator other.blocks[i]; }
+            }
+        }
+
+        impl<'a, const WORDS: usize> $assign_op<&'a FixedBitCode<WORDS>> for FixedBitCode<WORDS> {
+            fn $assign_method(&mut self, other: &'a FixedBitCode<WORDS>) {
+                self.$assign_method(*other);
+            }
+        }
+
+        impl<const WORDS: usize> $op for FixedBitCode<WORDS> {
+            type Output = FixedBitCode<WORDS>;
+            fn $method(mut self, other: FixedBitCode<WORDS>) -> FixedBitCode<WORDS> {
+                self.$assign_method(other);
+                self
+            }
+        }
+
+        impl<'a, 'b, const WORDS: usize> $op<&'b FixedBitCode<WORDS>> for &'a FixedBitCode<WORDS> {
+            type Output = FixedBitCode<WORDS>;
+            fn $method(self, other: &'b FixedBitCode<WORDS>) -> FixedBitCode<WORDS> {
+                let mut result = *self;
+                result.$assign_method(*other);
+                result
+            }
+        }
+    };
+}
+
+impl_fixed_binary_op!(BitAnd, bitand, BitAndAssign, bitand_assign, &);
+impl_fixed_binary_op!(BitOr, bitor, BitOrAssign, bitor_assign, |);
+impl_fixed_binary_op!(BitXor, bitxor, BitXorAssign, bitxor_assign, ^);
+
+
+impl<const WORDS: usize> Not for FixedBitCode<WORDS> {
+    type Output = FixedBitCode<WORDS>;
+    fn not(mut self) -> FixedBitCode<WORDS> {
+        for block in self.blocks.iter_mut() { *block = !*block; }
+        self.fix_last_block();
+        self
+    }
+}
+
+
+impl<const WORDS: usize> Not for &FixedBitCode<WORDS> {
+    type Output = FixedBitCode<WORDS>;
+    fn not(self) -> FixedBitCode<WORDS> {
+        !*self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code::{BitCode, BitCodeRef};
+    use error::BitCodeError;
+    use std::convert::TryFrom;
+    use super::{FixedBitCode, FixedBitCode128, FixedBitCode256};
+    use test::{black_box, Bencher};
+    use utils::random_bit_string;
+
+    #[test]
+    fn set_get() {
+        let mut bc = FixedBitCode256::new(200);
+        assert_eq!(bc.len(), 200);
+        assert_eq!(bc.count_ones(), 0);
+        bc.set(10, true);
+        bc.set(64, true);
+        bc.set(199, true);
+        assert_eq!(bc.count_ones(), 3);
+        assert_eq!(bc.get(64), Some(true));
+        assert_eq!(bc.get(65), Some(false));
+        assert_eq!(bc.get(200), None);
+        bc.set(64, false);
+        assert_eq!(bc.count_ones(), 2);
+        assert_eq!(bc.ones().collect::<Vec<usize>>(), vec![10, 199]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_checks_capacity() {
+        let _ = FixedBitCode128::new(129);
+    }
+
+    #[test]
+    fn agrees_with_bit_code() {
+        for &n in &[1, 63, 64, 100, 128, 200, 256] {
+            let s1 = random_bit_string(n);
+            let s2 = random_bit_string(n);
+            let (bc1, bc2) = (BitCode::from_bit_string(&s1), BitCode::from_bit_string(&s2));
+            let (fbc1, fbc2) = (FixedBitCode256::from_bit_string(&s1), FixedBitCode256::from_bit_string(&s2));
+            assert_eq!(fbc1.count_ones(), bc1.count_ones());
+            assert_eq!(fbc1.hamming_distance(&fbc2), bc1.hamming_distance(&bc2));
+            assert_eq!(fbc1.within(&fbc2, n / 2), bc1.within(&bc2, n / 2));
+            assert_eq!(fbc1.diff_positions(&fbc2), bc1.diff_positions(&bc2));
+            assert_eq!(fbc1.index_values(10), bc1.index_values(10));
+            assert_eq!(fbc1.substring(n / 3, 20), bc1.substring(n / 3, 20));
+            assert_eq!(fbc1.estimated_cosine(&fbc2), bc1.estimated_cosine(&bc2));
+            assert_eq!(FixedBitCode256::from_blocks(*fbc1.blocks(), n), fbc1);
+            assert_eq!(BitCode::from(&(fbc1 & fbc2)), &bc1 & &bc2);
+            assert_eq!(BitCode::from(&(fbc1 | fbc2)), &bc1 | &bc2);
+            assert_eq!(BitCode::from(&(fbc1 ^ fbc2)), &bc1 ^ &bc2);
+            assert_eq!(BitCode::from(&!fbc1), !&bc1);
+            // Round trip conversions.
+            assert_eq!(BitCode::from(&fbc1), bc1);
+            assert_eq!(FixedBitCode256::try_from(&bc1), Ok(fbc1));
+            assert_eq!(BitCodeRef::from(&fbc1), bc1.view());
+        }
+    }
+
+    #[test]
+    fn conversion_checks_capacity() {
+        let bc = BitCode::new(129);
+        assert_eq!(FixedBitCode128::try_from(&bc), Err(BitCodeError::CapacityExceeded { num_bits: 129, capacity: 128 }));
+        assert!(FixedBitCode::<3>::try_from(&bc).is_ok());
+    }
+
+    #[test]
+    fn majority() {
+        let bit_codes = vec![
+            FixedBitCode128::from_bit_string("1100"),
+            FixedBitCode128::from_bit_string("1010"),
+            FixedBitCode128::from_bit_string("1001"),
+        ];
+        assert_eq!(FixedBitCode128::majority(&bit_codes), Some(FixedBitCode128::from_bit_string("1000")));
+        assert_eq!(FixedBitCode128::majority(&[]), None);
+    }
+
+    #[bench]
+    fn within_256(b: &mut Bencher) {
+        let bc1 = FixedBitCode256::from_bit_string(&random_bit_string(256));
+        let bc2 = FixedBitCode256::from_bit_string(&random_bit_string(256));
+        b.iter(|| black_box(&bc1).within(black_box(&bc2), 32));
+    }
+}
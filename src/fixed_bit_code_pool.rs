@@ -0,0 +1,130 @@
+use bit_code_pool::{nearest, KnnMode, SearchResult};
+use encoding_options::EncodingOptions;
+use fixed_bit_code::FixedBitCode;
+use hamming;
+
+
+// A variant of BitCodePool holding fixed-width codes inline in one contiguous array, so linear scans read memory sequentially and the pool makes no allocation per code. Scans run through the batch kernels in hamming, over WORDS blocks per code.
+#[derive(Debug)]
+pub struct FixedBitCodePool<const WORDS: usize> {
+    blocks: Vec<[u64; WORDS]>,              // Blocks of the bit codes in the pool, stored inline; all codes have num_bits() bits.
+    ids: Vec<u64>,                          // Identifiers associated with bit codes (e.g. primary keys in database representation).
+    encoding_options: EncodingOptions,
+}
+
+
+impl<const WORDS: usize> FixedBitCodePool<WORDS> {
+    /// Returns an empty pool. Panics if the encoding options produce more bits than a FixedBitCode<WORDS> can hold.
+    pub fn new(encoding_options: EncodingOptions) -> Self {
+        let capacity = FixedBitCode::<WORDS>::CAPACITY;
+        assert!(encoding_options.num_bits() <= capacity, "{} bits do not fit in a {}-bit code", encoding_options.num_bits(), capacity);
+        FixedBitCodePool {
+            blocks: Vec::new(),
+            ids: Vec::new(),
+            encoding_options,
+        }
+    }
+
+    // Add a bit code created from a string to the pool.
+    pub fn add(&mut self, string: &str, id: u64) {
+        let bit_code = self.bit_code(string);
+        self.blocks.push(*bit_code.blocks());
+        self.ids.push(id);
+    }
+
+    // Return a bit code for a string, derived in the same way as bit codes in the pool.
+    pub fn bit_code(&self, string: &str) -> FixedBitCode<WORDS> {
+        FixedBitCode::from_string(string, &self.encoding_options)
+    }
+
+    // Get a copy of a FixedBitCode in the pool.
+    pub fn get(&self, i: usize) -> Option<FixedBitCode<WORDS>> {
+        self.blocks.get(i).map(|&blocks| FixedBitCode::from_blocks(blocks, self.num_bits()))
+    }
+
+    // Get the identifier associated with a bit code in the pool.
+    pub fn id(&self, i: usize) -> Option<u64> {
+        self.ids.get(i).cloned()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.encoding_options.num_bits()
+    }
+
+    /// Returns the indices of bit codes with Hamming distance <= radius from the needle.
+    pub fn search(&self, needle: &FixedBitCode<WORDS>, radius: usize) -> Vec<usize> {
+        self.scan(needle).enumerate().filter(|&(_, d)| d <= radius).map(|(i, _)| i).collect()
+    }
+
+    /// Returns the k nearest neighbors of the needle, including any ties with the kth, ordered by ascending distance.
+    pub fn search_knn(&self, needle: &FixedBitCode<WORDS>, k: usize) -> Vec<SearchResult> {
+        self.search_knn_with_mode(needle, k, KnnMode::IncludeTies)
+    }
+
+    /// Returns the k nearest neighbors of the needle, ordered by ascending distance, with ties at the kth distance treated according to mode.
+    pub fn search_knn_with_mode(&self, needle: &FixedBitCode<WORDS>, k: usize, mode: KnnMode) -> Vec<SearchResult> {
+        nearest(self.scan(needle).enumerate(), k, mode, self.num_bits())
+    }
+
+    // Iterate over the distances from the needle to each bit code in the pool, computed by the batch kernel.
+    fn scan(&self, needle: &FixedBitCode<WORDS>) -> hamming::Scan<'_> {
+        hamming::scan(needle.blocks(), self.blocks.as_flattened())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code_pool::BitCodePool;
+    use encoding_options::EncodingOptions;
+    use super::FixedBitCodePool;
+    use test::Bencher;
+    use utils::random_string;
+
+    #[test]
+    fn search_matches_bit_code_pool() {
+        let mut strings: Vec<String> = Vec::new();
+        for _ in 0..1_000 { strings.push(random_string(4)); }
+        let mut bit_code_pool = BitCodePool::new(EncodingOptions::default());
+        let mut fixed_bit_code_pool: FixedBitCodePool<4> = FixedBitCodePool::new(EncodingOptions::default());
+        for (i, string) in strings.iter().enumerate() {
+            bit_code_pool.add(string, i as u64);
+            fixed_bit_code_pool.add(string, i as u64);
+        }
+        assert_eq!(fixed_bit_code_pool.len(), bit_code_pool.len());
+        assert_eq!(fixed_bit_code_pool.id(10), Some(10));
+        for i in 0..10 {
+            let needle = bit_code_pool.get(i).unwrap();
+            let fixed_needle = &fixed_bit_code_pool.get(i).unwrap();
+            assert_eq!(fixed_bit_code_pool.search(fixed_needle, 80), bit_code_pool.search(needle, 80));
+            let results1 = bit_code_pool.search_knn(needle, 5);
+            let results2 = fixed_bit_code_pool.search_knn(fixed_needle, 5);
+            assert_eq!(results1, results2);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_checks_capacity() {
+        let _: FixedBitCodePool<2> = FixedBitCodePool::new(EncodingOptions::default());
+    }
+
+    #[bench]
+    fn search_fixed_bit_code_pool(b: &mut Bencher) {
+        // Few features and short n-grams keep encoding the strings cheap; the search only depends on the code length.
+        let mut pool: FixedBitCodePool<4> = FixedBitCodePool::new(EncodingOptions::new(true, vec![1, 2], 256, 100));
+        for id in 0..10_000 { pool.add(&random_string(10), id); }
+        let needle = pool.get(0).unwrap();
+        b.iter(|| pool.search(&needle, 64));
+    }
+}
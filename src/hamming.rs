@@ -0,0 +1,307 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::cmp::min;
+
+
+// Batch Hamming distance kernels. These compute the distances from one needle to a contiguous run of codes packed as in BitCodePool, i.e. each code occupies needle.len() consecutive u64 blocks. The fastest kernel supported by the CPU is selected at runtime: AVX-512 VPOPCNTDQ, then AVX2, then the POPCNT instruction, then a portable fallback.
+
+
+/// Returns the Hamming distance from the needle to each code in blocks.
+pub fn distances(needle: &[u64], blocks: &[u64]) -> Vec<u32> {
+    let mut distances = vec![0; blocks.len() / needle.len()];
+    distances_into(needle, blocks, &mut distances);
+    distances
+}
+
+
+/// Writes the Hamming distance from the needle to each code in blocks into distances. Panics if blocks does not hold exactly distances.len() codes of needle.len() blocks.
+pub fn distances_into(needle: &[u64], blocks: &[u64], distances: &mut [u32]) {
+    assert!(!needle.is_empty(), "needle has no blocks");
+    assert_eq!(blocks.len(), distances.len() * needle.len(), "blocks do not hold {} codes of {} blocks", distances.len(), needle.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512vpopcntdq") {
+            return unsafe { distances_avx512(needle, blocks, distances) };
+        }
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { distances_avx2(needle, blocks, distances) };
+        }
+        if is_x86_feature_detected!("popcnt") {
+            return unsafe { distances_popcnt(needle, blocks, distances) };
+        }
+    }
+    distances_portable(needle, blocks, distances)
+}
+
+
+/// Returns an iterator over the Hamming distances from the needle to each code in blocks. Distances are computed by the batch kernel a chunk of codes at a time, so scanning a large pool needs only a small buffer.
+pub fn scan<'a>(needle: &[u64], blocks: &'a [u64]) -> Scan<'a> {
+    assert!(!needle.is_empty(), "needle has no blocks");
+    assert_eq!(blocks.len() % needle.len(), 0, "blocks do not hold a whole number of codes of {} blocks", needle.len());
+    Scan { needle: needle.to_vec(), blocks, buffer: Vec::with_capacity(SCAN_CHUNK_CODES), position: 0 }
+}
+
+
+// Number of codes whose distances are computed per call to the batch kernel when scanning.
+const SCAN_CHUNK_CODES: usize = 1024;
+
+
+/// Iterator over batch-computed Hamming distances. See scan.
+pub struct Scan<'a> {
+    needle: Vec<u64>,
+    blocks: &'a [u64],                      // Blocks of the codes not yet passed to the kernel.
+    buffer: Vec<u32>,                       // Distances for the current chunk of codes.
+    position: usize,                        // Position of the next distance to return in the buffer.
+}
+
+
+impl<'a> ExactSizeIterator for Scan<'a> {}
+
+
+impl<'a> Iterator for Scan<'a> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        if self.position == self.buffer.len() {
+            if self.blocks.is_empty() { return None; }
+            let n = self.needle.len();
+            let num_codes = min(SCAN_CHUNK_CODES, self.blocks.len() / n);
+            let (chunk, rest) = self.blocks.split_at(num_codes * n);
+            self.buffer.resize(num_codes, 0);
+            distances_into(&self.needle, chunk, &mut self.buffer);
+            self.blocks = rest;
+            self.position = 0;
+        }
+        let d = self.buffer[self.position];
+        self.position += 1;
+        Some(d as usize)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.buffer.len() - self.position) + self.blocks.len() / self.needle.len();
+        (remaining, Some(remaining))
+    }
+}
+
+
+#[inline(always)]
+fn distances_portable(needle: &[u64], blocks: &[u64], distances: &mut [u32]) {
+    for (code, d) in blocks.chunks_exact(needle.len()).zip(distances.iter_mut()) {
+        let mut acc: u32 = 0;
+        for (block, needle_block) in code.iter().zip(needle) {
+            acc += (block ^ needle_block).count_ones();
+        }
+        *d = acc;
+    }
+}
+
+
+// The portable kernel, compiled so that count_ones uses the POPCNT instruction.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "popcnt")]
+unsafe fn distances_popcnt(needle: &[u64], blocks: &[u64], distances: &mut [u32]) {
+    distances_portable(needle, blocks, distances)
+}
+
+
+// Vector kernels work on `lanes` u64 blocks at a time regardless of code boundaries. The needle is tiled to a period that is a multiple of both the code length and the vector width, so every vector lines up with the needle blocks it is XORed against; per-block counts for each period are then summed per code. Codes left over after the last whole period are handled by the scalar kernel.
+#[cfg(target_arch = "x86_64")]
+struct Tiling {
+    pattern: Vec<u64>,
+    codes_per_period: usize,
+    num_periods: usize,
+}
+
+
+#[cfg(target_arch = "x86_64")]
+impl Tiling {
+    fn new(needle: &[u64], num_codes: usize, lanes: usize) -> Self {
+        let n = needle.len();
+        let period = n / gcd(n, lanes) * lanes;
+        Tiling {
+            pattern: (0..period).map(|i| needle[i % n]).collect(),
+            codes_per_period: period / n,
+            num_periods: num_codes / (period / n),
+        }
+    }
+
+    #[inline(always)]
+    fn period(&self) -> usize {
+        self.pattern.len()
+    }
+}
+
+
+#[cfg(target_arch = "x86_64")]
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn sum_counts(counts: &[u64], n: usize, distances: &mut [u32]) {
+    for (code_counts, d) in counts.chunks_exact(n).zip(distances.iter_mut()) {
+        *d = code_counts.iter().sum::<u64>() as u32;
+    }
+}
+
+
+// Counts the set bits in each u64 lane using the nibble lookup table method.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn popcount_epi64_avx2(v: __m256i) -> __m256i {
+    let lookup = _mm256_setr_epi8(
+        0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+        0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+    );
+    let low_mask = _mm256_set1_epi8(0x0f);
+    let lo = _mm256_and_si256(v, low_mask);
+    let hi = _mm256_and_si256(_mm256_srli_epi16(v, 4), low_mask);
+    let counts = _mm256_add_epi8(_mm256_shuffle_epi8(lookup, lo), _mm256_shuffle_epi8(lookup, hi));
+    // Sum the byte counts within each u64 lane.
+    _mm256_sad_epu8(counts, _mm256_setzero_si256())
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,popcnt")]
+unsafe fn distances_avx2(needle: &[u64], blocks: &[u64], distances: &mut [u32]) {
+    let n = needle.len();
+    let tiling = Tiling::new(needle, distances.len(), 4);
+    let period = tiling.period();
+    let mut counts: Vec<u64> = vec![0; period];
+    for p in 0..tiling.num_periods {
+        let words = blocks.as_ptr().add(p * period);
+        for v in (0..period).step_by(4) {
+            let x = _mm256_loadu_si256(words.add(v) as *const __m256i);
+            let y = _mm256_loadu_si256(tiling.pattern.as_ptr().add(v) as *const __m256i);
+            let c = popcount_epi64_avx2(_mm256_xor_si256(x, y));
+            _mm256_storeu_si256(counts.as_mut_ptr().add(v) as *mut __m256i, c);
+        }
+        let start = p * tiling.codes_per_period;
+        sum_counts(&counts, n, &mut distances[start..(start + tiling.codes_per_period)]);
+    }
+    let done = tiling.num_periods * tiling.codes_per_period;
+    distances_portable(needle, &blocks[(done * n)..], &mut distances[done..]);
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+unsafe fn distances_avx512(needle: &[u64], blocks: &[u64], distances: &mut [u32]) {
+    let n = needle.len();
+    let tiling = Tiling::new(needle, distances.len(), 8);
+    let period = tiling.period();
+    let mut counts: Vec<u64> = vec![0; period];
+    for p in 0..tiling.num_periods {
+        let words = blocks.as_ptr().add(p * period);
+        for v in (0..period).step_by(8) {
+            let x = _mm512_loadu_si512(words.add(v) as *const __m512i);
+            let y = _mm512_loadu_si512(tiling.pattern.as_ptr().add(v) as *const __m512i);
+            let c = _mm512_popcnt_epi64(_mm512_xor_si512(x, y));
+            _mm512_storeu_si512(counts.as_mut_ptr().add(v) as *mut __m512i, c);
+        }
+        let start = p * tiling.codes_per_period;
+        sum_counts(&counts, n, &mut distances[start..(start + tiling.codes_per_period)]);
+    }
+    let done = tiling.num_periods * tiling.codes_per_period;
+    distances_portable(needle, &blocks[(done * n)..], &mut distances[done..]);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+    use super::{distances, distances_portable, scan};
+    use test::{black_box, Bencher};
+
+    fn random_blocks(n: usize) -> Vec<u64> {
+        let mut rng = thread_rng();
+        (0..n).map(|_| rng.gen()).collect()
+    }
+
+    fn expected(needle: &[u64], blocks: &[u64]) -> Vec<u32> {
+        blocks.chunks(needle.len()).map(|code| {
+            code.iter().zip(needle).map(|(a, b)| (a ^ b).count_ones()).sum()
+        }).collect()
+    }
+
+    #[test]
+    fn kernels_agree() {
+        // Cover code lengths that do and do not divide the vector widths, and pools with partial periods.
+        for num_blocks in 1..18 {
+            for &num_codes in &[0, 1, 2, 3, 7, 8, 9, 33, 100] {
+                let needle = random_blocks(num_blocks);
+                let blocks = random_blocks(num_blocks * num_codes);
+                let correct = expected(&needle, &blocks);
+                assert_eq!(distances(&needle, &blocks), correct);
+                let mut portable = vec![0; num_codes];
+                distances_portable(&needle, &blocks, &mut portable);
+                assert_eq!(portable, correct);
+                #[cfg(target_arch = "x86_64")]
+                {
+                    if is_x86_feature_detected!("avx2") {
+                        let mut avx2 = vec![0; num_codes];
+                        unsafe { super::distances_avx2(&needle, &blocks, &mut avx2); }
+                        assert_eq!(avx2, correct);
+                    }
+                    if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512vpopcntdq") {
+                        let mut avx512 = vec![0; num_codes];
+                        unsafe { super::distances_avx512(&needle, &blocks, &mut avx512); }
+                        assert_eq!(avx512, correct);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scan_matches_distances() {
+        for &num_codes in &[0, 1, 1023, 1024, 1025, 3000] {
+            let needle = random_blocks(3);
+            let blocks = random_blocks(3 * num_codes);
+            let scanned: Vec<u32> = scan(&needle, &blocks).map(|d| d as u32).collect();
+            assert_eq!(scanned, expected(&needle, &blocks));
+            assert_eq!(scan(&needle, &blocks).len(), num_codes);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn distances_check_lengths() {
+        let _ = distances(&[0, 0], &[0, 0, 0]);
+    }
+
+    #[bench]
+    fn distances_256(b: &mut Bencher) {
+        let needle = random_blocks(4);
+        let blocks = random_blocks(4 * 10_000);
+        b.iter(|| distances(&needle, &blocks));
+    }
+
+    #[bench]
+    fn distances_256_portable(b: &mut Bencher) {
+        let needle = random_blocks(4);
+        let blocks = random_blocks(4 * 10_000);
+        let mut out = vec![0; 10_000];
+        b.iter(|| {
+            distances_portable(black_box(&needle), black_box(&blocks), &mut out);
+            black_box(&out);
+        });
+    }
+
+    #[bench]
+    fn distances_1024(b: &mut Bencher) {
+        let needle = random_blocks(16);
+        let blocks = random_blocks(16 * 10_000);
+        b.iter(|| distances(&needle, &blocks));
+    }
+}
@@ -1,14 +1,42 @@
 #![feature(test)]
 
-extern crate bit_vec;
 extern crate fnv;
 extern crate rand;
+extern crate rayon;
 extern crate test;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+
+
+// Logs a debug message when the log feature is enabled. Otherwise the arguments are type-checked but never evaluated.
+#[cfg(feature = "log")]
+macro_rules! debug_log {
+    ($($arg:tt)*) => { debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug_log {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
 
 pub mod bit_code;
 mod bit_code_index;
 pub mod bit_code_pool;
+pub mod calibration;
+pub mod dendrogram;
 pub mod encoding;
 pub mod encoding_options;
+pub mod entity_resolver;
+pub mod error;
+pub mod evaluation;
+pub mod fixed_bit_code;
+pub mod fixed_bit_code_pool;
+pub mod hamming;
+pub mod resolution;
+pub mod significance;
+pub mod similarity;
 pub mod string_features;
+mod union_find;
 pub mod utils;
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use union_find::UnionFind;
+use utils::FastHasher;
+
+
+/// A grouping of records into entities, each identified by the smallest record id among its members. Ids therefore depend only on the grouping, not on how or in what order it was computed, and an entity keeps its id across runs as long as its smallest member does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    entities: BTreeMap<u64, Vec<u64>>,      // Record ids in each entity, in ascending order.
+    entity_of: HashMap<u64, u64, FastHasher>,
+}
+
+
+impl Resolution {
+    /// Returns the resolution grouping records as in entity_sets, which must be disjoint sets of record ids.
+    pub fn from_entity_sets<I: IntoIterator<Item=Vec<u64>>>(entity_sets: I) -> Self {
+        let mut entities: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        let mut entity_of: HashMap<u64, u64, FastHasher> = HashMap::default();
+        for mut members in entity_sets {
+            if members.is_empty() { continue; }
+            members.sort_unstable();
+            members.dedup();
+            let entity_id = members[0];
+            for &id in &members {
+                let previous = entity_of.insert(id, entity_id);
+                assert!(previous.is_none(), "record {} is in more than one entity set", id);
+            }
+            entities.insert(entity_id, members);
+        }
+        Resolution { entities, entity_of }
+    }
+
+    /// Returns an iterator over the entities, as their ids and the ids of their records, both in ascending order.
+    pub fn entities(&self) -> impl Iterator<Item=(u64, &[u64])> {
+        self.entities.iter().map(|(&entity_id, members)| (entity_id, &members[..]))
+    }
+
+    /// Returns the id of the entity a record belongs to.
+    pub fn entity_of(&self, id: u64) -> Option<u64> {
+        self.entity_of.get(&id).cloned()
+    }
+
+    /// Returns the ids of the records in an entity, in ascending order.
+    pub fn members(&self, entity_id: u64) -> Option<&[u64]> {
+        self.entities.get(&entity_id).map(|members| &members[..])
+    }
+
+    /// Number of entities.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Returns how the entities changed from this resolution to a later one.
+    pub fn diff(&self, later: &Resolution) -> ResolutionDiff {
+        let mut diff = ResolutionDiff::default();
+        // Later entities sharing records with each earlier entity, and earlier entities sharing records with each later one.
+        let later_overlaps = overlaps(later, self);
+        let earlier_overlaps = overlaps(self, later);
+        for (&entity_id, earlier_ids) in &earlier_overlaps {
+            match earlier_ids.len() {
+                0 => diff.created.push(entity_id),
+                1 => {
+                    let earlier_id = earlier_ids[0];
+                    if later_overlaps[&earlier_id].len() == 1 {
+                        if self.entities[&earlier_id] == later.entities[&entity_id] {
+                            diff.unchanged.push(entity_id);
+                        } else {
+                            diff.updated.push((earlier_id, entity_id));
+                        }
+                    }
+                },
+                _ => diff.merged.push((entity_id, earlier_ids.clone())),
+            }
+        }
+        for (&entity_id, later_ids) in &later_overlaps {
+            match later_ids.len() {
+                0 => diff.removed.push(entity_id),
+                1 => {},
+                _ => diff.split.push((entity_id, later_ids.clone())),
+            }
+        }
+        diff
+    }
+}
+
+
+// Returns, for each entity of to, the ids of the entities of from that share records with it, in ascending order.
+fn overlaps(from: &Resolution, to: &Resolution) -> BTreeMap<u64, Vec<u64>> {
+    to.entities.iter().map(|(&entity_id, members)| {
+        let mut ids: Vec<u64> = members.iter().filter_map(|&id| from.entity_of(id)).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        (entity_id, ids)
+    }).collect()
+}
+
+
+/// Changes between two resolutions of records into entities. See Resolution::diff. Each later entity is created, unchanged, updated, merged, or only part of a split earlier entity, and may be both merged and part of a split, e.g. when part of one earlier entity joins another. All lists are in ascending order of entity id.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolutionDiff {
+    created: Vec<u64>,
+    removed: Vec<u64>,
+    unchanged: Vec<u64>,
+    updated: Vec<(u64, u64)>,
+    merged: Vec<(u64, Vec<u64>)>,
+    split: Vec<(u64, Vec<u64>)>,
+}
+
+
+impl ResolutionDiff {
+    /// Later entities none of whose records were in the earlier resolution.
+    pub fn created(&self) -> &[u64] { &self.created }
+
+    /// Earlier entities none of whose records are in the later resolution.
+    pub fn removed(&self) -> &[u64] { &self.removed }
+
+    /// Entities with exactly the same records in both resolutions.
+    pub fn unchanged(&self) -> &[u64] { &self.unchanged }
+
+    /// Entities that correspond one to one but gained or lost records, as (earlier id, later id). The ids differ if the smallest member changed.
+    pub fn updated(&self) -> &[(u64, u64)] { &self.updated }
+
+    /// Later entities containing records of several earlier entities, as (later id, earlier ids).
+    pub fn merged(&self) -> &[(u64, Vec<u64>)] { &self.merged }
+
+    /// Earlier entities whose records are in several later entities, as (earlier id, later ids).
+    pub fn split(&self) -> &[(u64, Vec<u64>)] { &self.split }
+
+    /// Whether the resolutions group records identically.
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.removed.is_empty() && self.updated.is_empty() && self.merged.is_empty() && self.split.is_empty()
+    }
+}
+
+
+/// Pairs of records that entity resolution must place in the same entity (must-link) or in different entities (cannot-link), whatever their bit codes, e.g. decisions from manual review.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Constraints {
+    must_link: Vec<(u64, u64)>,
+    cannot_link: Vec<(u64, u64)>,
+}
+
+
+impl Constraints {
+    pub fn new() -> Self {
+        Constraints::default()
+    }
+
+    /// Requires the records with ids a and b to be in the same entity.
+    pub fn must_link(mut self, a: u64, b: u64) -> Self {
+        self.must_link.push((a, b));
+        self
+    }
+
+    /// Requires the records with ids a and b to be in different entities.
+    pub fn cannot_link(mut self, a: u64, b: u64) -> Self {
+        self.cannot_link.push((a, b));
+        self
+    }
+
+    pub fn must_links(&self) -> &[(u64, u64)] { &self.must_link }
+
+    pub fn cannot_links(&self) -> &[(u64, u64)] { &self.cannot_link }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.must_link.is_empty() && self.cannot_link.is_empty()
+    }
+}
+
+
+// Disjoint sets of positions that start from the must-link components and refuse any union that would join a cannot-link pair.
+#[derive(Debug)]
+pub(crate) struct ConstrainedSets {
+    union_find: UnionFind,
+    members: Vec<Vec<usize>>,               // Members of the set each representative stands for.
+    forbidden: Vec<Vec<usize>>,             // Positions cannot-linked to a member of the set each representative stands for.
+    has_cannot_links: bool,
+}
+
+
+impl ConstrainedSets {
+    // Returns the sets over 0..n joined by the must-link pairs, or a cannot-link pair that the must-link pairs join.
+    pub fn new(n: usize, must_link: &[(usize, usize)], cannot_link: &[(usize, usize)]) -> Result<Self, (usize, usize)> {
+        let mut sets = ConstrainedSets {
+            union_find: UnionFind::new(n),
+            members: (0..n).map(|i| vec![i]).collect(),
+            forbidden: vec![Vec::new(); n],
+            has_cannot_links: !cannot_link.is_empty(),
+        };
+        for &(a, b) in must_link { sets.merge(a, b); }
+        for &(a, b) in cannot_link {
+            if sets.find(a) == sets.find(b) { return Err((a, b)); }
+            let (root_a, root_b) = (sets.find(a), sets.find(b));
+            sets.forbidden[root_a].push(b);
+            sets.forbidden[root_b].push(a);
+        }
+        Ok(sets)
+    }
+
+    #[inline]
+    pub fn find(&mut self, i: usize) -> usize {
+        self.union_find.find(i)
+    }
+
+    #[inline]
+    pub fn has_cannot_links(&self) -> bool {
+        self.has_cannot_links
+    }
+
+    // Members of the set containing i.
+    pub fn members(&mut self, i: usize) -> &[usize] {
+        let root = self.find(i);
+        &self.members[root]
+    }
+
+    // Joins the sets containing i and j unless that would join a cannot-link pair. Returns whether they were joined.
+    pub fn union(&mut self, i: usize, j: usize) -> bool {
+        let (root_i, root_j) = (self.find(i), self.find(j));
+        if root_i == root_j { return false; }
+        let forbidden_i = mem::take(&mut self.forbidden[root_i]);
+        let conflict = forbidden_i.iter().any(|&c| self.union_find.find(c) == root_j);
+        self.forbidden[root_i] = forbidden_i;
+        if conflict { return false; }
+        self.merge(i, j)
+    }
+
+    // Joins the sets containing i and j regardless of cannot-links.
+    fn merge(&mut self, i: usize, j: usize) -> bool {
+        let (root_i, root_j) = (self.find(i), self.find(j));
+        if !self.union_find.union(root_i, root_j) { return false; }
+        let (root, other) = if self.find(root_i) == root_i { (root_i, root_j) } else { (root_j, root_i) };
+        for field in [&mut self.members, &mut self.forbidden] {
+            let mut moved = mem::take(&mut field[other]);
+            if moved.len() > field[root].len() { mem::swap(&mut moved, &mut field[root]); }
+            field[root].extend(moved);
+        }
+        true
+    }
+
+    // Returns the sets, each in ascending order, ordered by their smallest member.
+    pub fn groups(&mut self) -> Vec<Vec<usize>> {
+        self.union_find.groups()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ConstrainedSets, Resolution};
+
+    #[test]
+    fn stable_ids() {
+        let resolution = Resolution::from_entity_sets(vec![vec![7, 3, 9], vec![5], vec![]]);
+        assert_eq!(resolution.len(), 2);
+        assert_eq!(resolution.entities().collect::<Vec<(u64, &[u64])>>(), vec![(3, &[3, 7, 9][..]), (5, &[5][..])]);
+        assert_eq!(resolution.entity_of(9), Some(3));
+        assert_eq!(resolution.entity_of(4), None);
+        assert_eq!(resolution.members(5), Some(&[5][..]));
+        // Ids depend only on the grouping.
+        assert_eq!(Resolution::from_entity_sets(vec![vec![5], vec![9, 7, 3]]), resolution);
+    }
+
+    #[test]
+    fn diff() {
+        let earlier = Resolution::from_entity_sets(vec![vec![1, 2], vec![3, 4], vec![5, 6], vec![7, 8, 9], vec![10], vec![11, 12]]);
+        let later = Resolution::from_entity_sets(vec![vec![1, 2], vec![3, 4, 5, 6], vec![7], vec![8, 9], vec![11, 12, 13], vec![20]]);
+        let diff = earlier.diff(&later);
+        assert_eq!(diff.unchanged(), &[1]);
+        assert_eq!(diff.merged(), &[(3, vec![3, 5])]);
+        assert_eq!(diff.split(), &[(7, vec![7, 8])]);
+        assert_eq!(diff.updated(), &[(11, 11)]);
+        assert_eq!(diff.created(), &[20]);
+        assert_eq!(diff.removed(), &[10]);
+        assert!(!diff.is_empty());
+        assert!(earlier.diff(&earlier).is_empty());
+        assert_eq!(earlier.diff(&earlier).unchanged().len(), earlier.len());
+    }
+
+    #[test]
+    fn constrained_sets() {
+        assert_eq!(ConstrainedSets::new(4, &[(0, 1), (1, 2)], &[(2, 0)]).unwrap_err(), (2, 0));
+        let mut sets = ConstrainedSets::new(6, &[(0, 1)], &[(1, 2), (4, 5)]).unwrap();
+        assert!(sets.has_cannot_links());
+        let mut members = sets.members(1).to_vec();
+        members.sort();
+        assert_eq!(members, vec![0, 1]);
+        assert!(!sets.union(0, 2));
+        assert!(sets.union(2, 3));
+        assert!(!sets.union(3, 0));
+        assert!(sets.union(3, 4));
+        assert!(!sets.union(2, 5));
+        assert!(sets.union(5, 0));
+        assert_eq!(sets.groups(), vec![vec![0, 1, 5], vec![2, 3, 4]]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlapping_entity_sets() {
+        Resolution::from_entity_sets(vec![vec![1, 2], vec![2, 3]]);
+    }
+}
@@ -0,0 +1,116 @@
+use bit_code_pool::BitCodePool;
+use rand::{Rng, SeedableRng};
+use rand::isaac::Isaac64Rng;
+
+
+/// Returns the probability that two independent, uniformly random bit codes of num_bits bits are within distance bits of one another: the lower tail of the Binomial(num_bits, 1/2) distribution. This is the p-value of observing the distance between unrelated codes.
+pub fn p_value(distance: usize, num_bits: usize) -> f64 {
+    if distance >= num_bits { return 1.0; }
+    // Sum the terms C(n, k) / 2^n in log space, as they underflow for long codes.
+    let ln_half = 0.5f64.ln();
+    let mut ln_term = num_bits as f64 * ln_half;
+    let mut ln_terms: Vec<f64> = Vec::with_capacity(distance + 1);
+    for k in 0..(distance + 1) {
+        ln_terms.push(ln_term);
+        ln_term += ((num_bits - k) as f64).ln() - ((k + 1) as f64).ln();
+    }
+    let ln_max = ln_terms[distance];
+    let sum: f64 = ln_terms.iter().map(|&ln_term| (ln_term - ln_max).exp()).sum();
+    (ln_max + sum.ln()).exp().min(1.0)
+}
+
+
+/// Returns the largest radius at which the p-value of num_bits-bit codes is at most the significance level, or None if even identical codes are not that unlikely. Searching a pool of n codes makes n comparisons, so divide the significance level by n to bound the chance of any unrelated code being found.
+pub fn radius_for_significance(num_bits: usize, significance: f64) -> Option<usize> {
+    (0..(num_bits + 1)).take_while(|&distance| p_value(distance, num_bits) <= significance).last()
+}
+
+
+/// The distribution of Hamming distances between random pairs of codes in a pool, as an empirical null for distances that accounts for the codes not being uniformly random.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmpiricalNull {
+    cumulative_counts: Vec<usize>,          // Number of sampled pairs within each distance from 0 to the number of bits.
+    num_samples: usize,
+}
+
+
+impl EmpiricalNull {
+    /// Samples num_samples pairs of distinct codes from the pool, with replacement, using a seeded generator so that the null is reproducible.
+    pub fn sample(pool: &BitCodePool, num_samples: usize, seed: u64) -> Self {
+        let num_bits = pool.num_bits();
+        let mut counts: Vec<usize> = vec![0; num_bits + 1];
+        let num_samples = if pool.len() < 2 { 0 } else { num_samples };
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+        for _ in 0..num_samples {
+            let i = rng.gen_range(0, pool.len());
+            // Pick j uniformly from the other codes.
+            let mut j = rng.gen_range(0, pool.len() - 1);
+            if j >= i { j += 1; }
+            counts[pool.get(i).unwrap().hamming_distance(pool.get(j).unwrap())] += 1;
+        }
+        let mut cumulative_counts = counts;
+        for distance in 1..cumulative_counts.len() { cumulative_counts[distance] += cumulative_counts[distance - 1]; }
+        EmpiricalNull { cumulative_counts, num_samples }
+    }
+
+    /// Number of sampled pairs within each distance, from 0 to the number of bits.
+    pub fn cumulative_counts(&self) -> &[usize] {
+        &self.cumulative_counts
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    /// Returns the estimated probability that a random pair of codes in the pool is within distance bits of one another. One is added to the count and the number of samples, so that the estimate is never zero.
+    pub fn p_value(&self, distance: usize) -> f64 {
+        let distance = distance.min(self.cumulative_counts.len() - 1);
+        (self.cumulative_counts[distance] + 1) as f64 / (self.num_samples + 1) as f64
+    }
+
+    /// Returns the largest radius at which the empirical p-value is at most the significance level, or None if there is none. Resolving significance levels below 1 / (samples + 1) needs more samples.
+    pub fn radius_for_significance(&self, significance: f64) -> Option<usize> {
+        (0..self.cumulative_counts.len()).take_while(|&distance| self.p_value(distance) <= significance).last()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bit_code_pool::BitCodePool;
+    use encoding_options::EncodingOptions;
+    use super::{p_value, radius_for_significance, EmpiricalNull};
+    use utils::random_string;
+
+    #[test]
+    fn binomial_p_value() {
+        assert_eq!(p_value(0, 4), 1.0 / 16.0);
+        assert_eq!(p_value(1, 4), 5.0 / 16.0);
+        assert_eq!(p_value(4, 4), 1.0);
+        // Half the distribution, plus half of the central term C(256, 128) / 2^256.
+        assert!((p_value(128, 256) - 0.524_91).abs() < 1e-5);
+        assert!(p_value(10, 256) > 0.0 && p_value(10, 256) < 1e-50);
+        assert_eq!(radius_for_significance(4, 0.1), Some(0));
+        assert_eq!(radius_for_significance(4, 0.01), None);
+        let radius = radius_for_significance(256, 1e-6).unwrap();
+        assert!(p_value(radius, 256) <= 1e-6 && p_value(radius + 1, 256) > 1e-6);
+    }
+
+    #[test]
+    fn empirical_null() {
+        let mut pool = BitCodePool::new(EncodingOptions::new(true, vec![1, 2], 64, 200));
+        for id in 0..500 { pool.add(&random_string(5), id); }
+        let null = EmpiricalNull::sample(&pool, 10_000, 7);
+        assert_eq!(null, EmpiricalNull::sample(&pool, 10_000, 7));
+        assert_eq!(null.num_samples(), 10_000);
+        assert_eq!(null.cumulative_counts().len(), 65);
+        assert_eq!(*null.cumulative_counts().last().unwrap(), 10_000);
+        assert_eq!(null.p_value(64), 1.0);
+        assert!(null.p_value(0) >= 1.0 / 10_001.0);
+        if let Some(radius) = null.radius_for_significance(0.01) {
+            assert!(null.p_value(radius) <= 0.01 && null.p_value(radius + 1) > 0.01);
+        }
+        let empty = EmpiricalNull::sample(&BitCodePool::new(EncodingOptions::new(true, vec![1], 64, 200)), 100, 7);
+        assert_eq!((empty.num_samples(), empty.p_value(0)), (0, 1.0));
+    }
+}
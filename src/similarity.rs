@@ -0,0 +1,80 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+
+/// An estimate of the cosine similarity of the feature vectors behind two bit codes, with a confidence interval. Each bit is the sign of a random projection, so two vectors at angle θ differ at each bit with probability θ / π, and a distance of d bits out of n estimates the cosine as cos(π·d/n).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CosineEstimate {
+    estimate: f64,
+    lower: f64,
+    upper: f64,
+}
+
+
+impl CosineEstimate {
+    pub fn estimate(&self) -> f64 { self.estimate }
+
+    /// Lower bound of the confidence interval.
+    pub fn lower(&self) -> f64 { self.lower }
+
+    /// Upper bound of the confidence interval.
+    pub fn upper(&self) -> f64 { self.upper }
+}
+
+
+impl fmt::Display for CosineEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.4} ({:.4} to {:.4})", self.estimate, self.lower, self.upper)
+    }
+}
+
+
+/// Estimates the cosine similarity from a distance of distance bits between num_bits-bit codes, with a 95% confidence interval.
+pub fn estimated_cosine(distance: usize, num_bits: usize) -> CosineEstimate {
+    estimated_cosine_with_z(distance, num_bits, Z_95)
+}
+
+
+/// As estimated_cosine, with the confidence interval z standard deviations wide, e.g. 2.576 for 99%.
+// The interval is the Wilson score interval for the probability that a bit differs, mapped through cos(π·p), which is decreasing in p.
+pub fn estimated_cosine_with_z(distance: usize, num_bits: usize, z: f64) -> CosineEstimate {
+    if num_bits == 0 { return CosineEstimate { estimate: 1.0, lower: -1.0, upper: 1.0 }; }
+    let n = num_bits as f64;
+    let p = (distance as f64 / n).min(1.0);
+    let z2 = z * z;
+    let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half_width = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    let cosine = |p: f64| (PI * p.clamp(0.0, 1.0)).cos();
+    CosineEstimate { estimate: cosine(p), lower: cosine(centre + half_width), upper: cosine(centre - half_width) }
+}
+
+
+// Standard normal quantile for a two-sided 95% interval.
+const Z_95: f64 = 1.959_964;
+
+
+#[cfg(test)]
+mod tests {
+    use super::{estimated_cosine, estimated_cosine_with_z};
+
+    #[test]
+    fn cosine_estimates() {
+        let identical = estimated_cosine(0, 256);
+        assert_eq!(identical.estimate(), 1.0);
+        assert_eq!(identical.upper(), 1.0);
+        assert!(identical.lower() > 0.99 && identical.lower() < 1.0);
+        let orthogonal = estimated_cosine(128, 256);
+        assert!(orthogonal.estimate().abs() < 1e-12);
+        assert!(orthogonal.lower() < 0.0 && orthogonal.upper() > 0.0);
+        assert!((orthogonal.lower() + orthogonal.upper()).abs() < 1e-12);
+        assert_eq!(estimated_cosine(256, 256).estimate(), -1.0);
+        // The interval narrows with more bits and widens with more confidence.
+        let short = estimated_cosine(16, 64);
+        let long = estimated_cosine(64, 256);
+        assert!((short.estimate() - long.estimate()).abs() < 1e-12);
+        assert!(long.upper() - long.lower() < short.upper() - short.lower());
+        let wide = estimated_cosine_with_z(16, 64, 2.576);
+        assert!(wide.lower() < short.lower() && wide.upper() > short.upper());
+        assert_eq!(format!("{}", estimated_cosine(0, 0)), "1.0000 (-1.0000 to 1.0000)");
+    }
+}
@@ -57,7 +57,7 @@ mod tests {
         b.iter(|| {
             let string_features = get_string_features(&random_string, &vec![2, 3, 4, 5, 6, 7]);
             let mut sum: u64 = 0;
-            for (hash_value, _) in string_features { sum = sum ^ hash_value };
+            for (hash_value, _) in string_features { sum ^= hash_value };
             sum
         })
     }
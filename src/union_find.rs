@@ -0,0 +1,83 @@
+// Disjoint sets over 0..n, with union by size and path halving.
+#[derive(Clone, Debug)]
+pub struct UnionFind {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+
+impl UnionFind {
+    pub fn new(n: usize) -> Self {
+        UnionFind { parents: (0..n).collect(), sizes: vec![1; n] }
+    }
+
+    // Adds a new singleton set, returning its element.
+    pub fn add(&mut self) -> usize {
+        self.parents.push(self.parents.len());
+        self.sizes.push(1);
+        self.parents.len() - 1
+    }
+
+    // Returns the representative of the set containing i, without compressing paths.
+    pub fn root(&self, mut i: usize) -> usize {
+        while self.parents[i] != i { i = self.parents[i]; }
+        i
+    }
+
+    // Returns the representative of the set containing i.
+    pub fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    // Merges the sets containing i and j, returning false if they were already the same set.
+    pub fn union(&mut self, i: usize, j: usize) -> bool {
+        let (mut a, mut b) = (self.find(i), self.find(j));
+        if a == b { return false; }
+        if self.sizes[a] < self.sizes[b] { ::std::mem::swap(&mut a, &mut b); }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+        true
+    }
+
+    // Returns the sets, each in ascending order, ordered by their smallest member.
+    pub fn groups(&mut self) -> Vec<Vec<usize>> {
+        let n = self.parents.len();
+        let mut group_of_root: Vec<usize> = vec![usize::MAX; n];
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for i in 0..n {
+            let root = self.find(i);
+            if group_of_root[root] == usize::MAX {
+                group_of_root[root] = groups.len();
+                groups.push(Vec::new());
+            }
+            groups[group_of_root[root]].push(i);
+        }
+        groups
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::UnionFind;
+
+    #[test]
+    fn union_find() {
+        let mut union_find = UnionFind::new(6);
+        assert!(union_find.union(4, 1));
+        assert!(union_find.union(1, 5));
+        assert!(!union_find.union(5, 4));
+        assert!(union_find.union(3, 0));
+        assert_eq!(union_find.find(4), union_find.find(5));
+        assert_ne!(union_find.find(4), union_find.find(0));
+        assert_eq!(union_find.groups(), vec![vec![0, 3], vec![1, 4, 5], vec![2]]);
+        assert_eq!(union_find.add(), 6);
+        assert!(union_find.union(6, 2));
+        assert_eq!(union_find.root(6), union_find.find(2));
+        assert_eq!(union_find.groups(), vec![vec![0, 3], vec![1, 4, 5], vec![2, 6]]);
+    }
+}
@@ -15,7 +15,7 @@ pub fn get_num_indexes(num_bits: usize, bits_per_index: usize) -> usize {
 
 #[inline]
 pub fn num_blocks_needed(num_bits: usize) -> usize {
-    let mut num_blocks = ((num_bits - 1) / 64) + 1;
+    let mut num_blocks = num_bits.div_ceil(64);
     if num_blocks == 0 { num_blocks = 1 };
     num_blocks
 }